@@ -135,7 +135,7 @@ mod deserialize {
         type Value = ReturnValue;
 
         fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "an integer, byte string, list or map")
+            write!(f, "an integer, string, list or map")
         }
 
         fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
@@ -154,6 +154,10 @@ mod deserialize {
             Ok(ReturnValue::String(s))
         }
 
+        fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+            Ok(ReturnValue::String(v.to_string()))
+        }
+
         fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
             let mut res = Vec::with_capacity(seq.size_hint().unwrap_or_default());
 
@@ -17,6 +17,8 @@ mod errors;
 mod func_args;
 mod func_list;
 mod func_ret;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod msgs;
 mod txid;
 
@@ -0,0 +1,416 @@
+//! In-process mock of the cjdns router, for testing without a running cjdns.
+//!
+//! Enabled with the `mock` feature. Serves a scripted admin API over UDP bencode on a random local port:
+//! `ping`, `cookie`/`auth`, `Admin_availableFunctions`, `AuthorizedPasswords_list`, `Core_nodeInfo`
+//! and `UpperDistributor_registerHandler`/`UpperDistributor_listHandlers`/`UpperDistributor_unregisterHandler`.
+//!
+//! Like the real router, the mock exchanges route-header-framed packets with the registered handlers:
+//! packets can be injected to the handlers of a content type, and the packets handlers send
+//! to the [inject address](struct.MockRouter.html#method.inject_addr) are captured.
+//!
+//! # Example
+//! ```no_run
+//! # use cjdns_admin::mock::{MockConfig, MockRouter};
+//! # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+//! let router = MockRouter::start(MockConfig::default()).await?;
+//! let mut conn = cjdns_admin::connect(Some(router.opts())).await?;
+//! assert!(conn.functions.find("Core_nodeInfo").is_some());
+//! # Ok(())}
+//! ```
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::net::udp::{RecvHalf, SendHalf};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::{io, select, task};
+
+use bencode::BValue;
+use cjdns_crypto::hash::sha256;
+
+use crate::config::Opts;
+
+/// Function argument as `(name, required, type)`.
+type FunctionArg = (&'static str, bool, &'static str);
+
+/// Functions served by the mock with their arguments.
+const FUNCTIONS: &[(&str, &[FunctionArg])] = &[
+    ("Admin_availableFunctions", &[("page", false, "Int")]),
+    ("AuthorizedPasswords_list", &[]),
+    ("Core_nodeInfo", &[]),
+    ("UpperDistributor_listHandlers", &[("page", true, "Int")]),
+    ("UpperDistributor_registerHandler", &[("contentType", true, "Int"), ("udpPort", true, "Int")]),
+    ("UpperDistributor_unregisterHandler", &[("udpPort", true, "Int")]),
+    ("ping", &[]),
+];
+
+/// Functions which can be called without authentication.
+const NO_AUTH_FUNCTIONS: &[&str] = &["Admin_availableFunctions", "cookie", "ping"];
+
+/// Number of functions per `Admin_availableFunctions` page, small enough for the list to take a few pages.
+const FUNCTIONS_PAGE_SIZE: usize = 4;
+
+/// Number of handlers per `UpperDistributor_listHandlers` page.
+const HANDLERS_PAGE_SIZE: usize = 16;
+
+/// Mock router settings.
+#[derive(Clone, Debug)]
+pub struct MockConfig {
+    /// Admin password. If set, calls without valid authentication are rejected,
+    /// except for `ping`, `cookie` and `Admin_availableFunctions`.
+    pub password: Option<String>,
+
+    /// Node name returned by `Core_nodeInfo` as `myAddr`.
+    pub my_addr: String,
+
+    /// Encoding scheme returned by `Core_nodeInfo`.
+    pub encoding_scheme: Vec<MockEncodingForm>,
+}
+
+/// Encoding scheme form, as returned by `Core_nodeInfo`.
+#[derive(Clone, Debug)]
+pub struct MockEncodingForm {
+    /// Number of bits in the label, excluding the prefix.
+    pub bit_count: u8,
+    /// Number of bits in the prefix.
+    pub prefix_len: u8,
+    /// Prefix as a hex string.
+    pub prefix: String,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        let form = |bit_count, prefix_len, prefix: &str| MockEncodingForm {
+            bit_count,
+            prefix_len,
+            prefix: prefix.to_string(),
+        };
+        MockConfig {
+            password: Some("NONE".to_string()),
+            my_addr: "v21.0000.0000.0000.0001.z15pzyd9wgzs2g5np7d3swrqc1533yb7xx9dq0pvrqrqs42uwgq0.k".to_string(),
+            // The v358 scheme
+            encoding_scheme: vec![form(3, 1, "01"), form(5, 2, "02"), form(8, 2, "00")],
+        }
+    }
+}
+
+/// Running mock router. The admin API is served until this is dropped.
+pub struct MockRouter {
+    admin_addr: SocketAddr,
+    password: Option<String>,
+    state: Arc<Mutex<MockState>>,
+    inject_addr: SocketAddr,
+    data_recv: RecvHalf,
+    data_send: SendHalf,
+    _shutdown: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Registered handlers as `(content type, UDP port)`, in the order of registration
+    handlers: Vec<(u32, u16)>,
+    /// Cookies given out and not used yet
+    cookies: HashSet<String>,
+    next_cookie: u64,
+    /// Names of the functions called so far, in order
+    calls: Vec<String>,
+}
+
+impl MockRouter {
+    /// Start serving the admin API on a random UDP port on `127.0.0.1`.
+    pub async fn start(config: MockConfig) -> io::Result<Self> {
+        let admin_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let admin_addr = admin_socket.local_addr()?;
+        let data_socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await?;
+        let inject_addr = data_socket.local_addr()?;
+        let (data_recv, data_send) = data_socket.split();
+
+        let password = config.password.clone();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        task::spawn(serve_admin(admin_socket, config, state.clone(), shutdown_rx));
+
+        Ok(MockRouter {
+            admin_addr,
+            password,
+            state,
+            inject_addr,
+            data_recv,
+            data_send,
+            _shutdown: shutdown_tx,
+        })
+    }
+
+    /// Options for `cjdns_admin::connect()` to connect to this router.
+    pub fn opts(&self) -> Opts {
+        Opts {
+            addr: Some(self.admin_addr.ip().to_string()),
+            port: Some(self.admin_addr.port()),
+            password: self.password.clone(),
+            config_file_path: None,
+            anon: self.password.is_none(),
+        }
+    }
+
+    /// Address of the admin API.
+    pub fn admin_addr(&self) -> SocketAddr {
+        self.admin_addr
+    }
+
+    /// Address the handlers send packets to, in place of the `[fc00::1]:1` intercepted by the real router.
+    pub fn inject_addr(&self) -> SocketAddr {
+        self.inject_addr
+    }
+
+    /// Currently registered handlers as `(content type, UDP port)`.
+    pub fn handlers(&self) -> Vec<(u32, u16)> {
+        self.state.lock().expect("mock state").handlers.clone()
+    }
+
+    /// Names of the admin functions called so far, in order. Authenticated calls are listed by the called function name.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().expect("mock state").calls.clone()
+    }
+
+    /// Send the packet (route header, data header and content) to every handler of the content type
+    /// on the IPv6 loopback. Returns the number of handlers it was sent to.
+    pub async fn inject(&mut self, content_type: u32, packet: &[u8]) -> io::Result<usize> {
+        let ports = self
+            .handlers()
+            .into_iter()
+            .filter(|&(handler_type, _)| handler_type == content_type)
+            .map(|(_, port)| port)
+            .collect::<Vec<_>>();
+        for &port in ports.iter() {
+            let addr = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+            self.data_send.send_to(packet, &addr).await?;
+        }
+        Ok(ports.len())
+    }
+
+    /// Wait for the next packet sent by a handler to the inject address.
+    pub async fn capture(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = [0; 1500];
+        let (size, _) = self.data_recv.recv_from(&mut buf).await?;
+        Ok(buf[..size].to_vec())
+    }
+}
+
+async fn serve_admin(mut socket: UdpSocket, config: MockConfig, state: Arc<Mutex<MockState>>, mut shutdown: oneshot::Receiver<()>) {
+    let mut buf = [0; 1500];
+    loop {
+        let (size, from) = select! {
+            res = socket.recv_from(&mut buf) => match res {
+                Ok(res) => res,
+                Err(_) => return,
+            },
+            _ = &mut shutdown => return,
+        };
+        let reply = handle_query(&config, &mut state.lock().expect("mock state"), &buf[..size]);
+        if let Some(reply) = reply.and_then(|reply| reply.encode().ok()) {
+            if socket.send_to(&reply, &from).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Reply to the bencoded query, `None` if it isn't a valid query.
+fn handle_query(config: &MockConfig, state: &mut MockState, data: &[u8]) -> Option<BValue> {
+    let query = BValue::decode(data).ok()?;
+    let txid = query.get_dict_value("txid").ok().flatten();
+    let q = query.get_dict_value_str("q").ok()?;
+    let args = query.get_dict_value("args").ok().flatten();
+
+    let (func, authenticated) = if q == "auth" {
+        let func = query.get_dict_value_str("aq").ok()?;
+        (func, is_auth_valid(config, state, &query, data))
+    } else {
+        (q, false)
+    };
+    state.calls.push(func.clone());
+
+    let mut reply = if config.password.is_some() && !authenticated && !NO_AUTH_FUNCTIONS.contains(&func.as_str()) {
+        error_reply("Auth failed.")
+    } else {
+        call_function(config, state, &func, args.as_ref())
+    };
+    if let Some(txid) = txid {
+        reply.set_dict_value("txid", txid).expect("dict reply");
+    }
+    Some(reply)
+}
+
+/// Check the `auth` query the way cjdns does: the `hash` entry must be the hash of the whole message
+/// with the `hash` entry set to the hash of the password and the cookie.
+fn is_auth_valid(config: &MockConfig, state: &mut MockState, query: &BValue, data: &[u8]) -> bool {
+    let password = match config.password.as_ref() {
+        Some(password) => password,
+        None => return true,
+    };
+    let (cookie, hash) = match (query.get_dict_value_str("cookie"), query.get_dict_value_str("hash")) {
+        (Ok(cookie), Ok(hash)) => (cookie, hash),
+        _ => return false,
+    };
+    if !state.cookies.remove(&cookie) {
+        return false;
+    }
+
+    const HASH_ENTRY: &[u8] = b"4:hash64:";
+    let pos = match data.windows(HASH_ENTRY.len()).position(|w| w == HASH_ENTRY) {
+        Some(pos) if data.len() >= pos + HASH_ENTRY.len() + 64 => pos + HASH_ENTRY.len(),
+        _ => return false,
+    };
+    let passwd_hash = hex::encode(sha256::hash((password.clone() + &cookie).as_bytes()));
+    let mut msg = data.to_vec();
+    msg[pos..pos + 64].copy_from_slice(passwd_hash.as_bytes());
+    hex::encode(sha256::hash(&msg)) == hash
+}
+
+fn call_function(config: &MockConfig, state: &mut MockState, func: &str, args: Option<&BValue>) -> BValue {
+    let int_arg = |name: &str| args.and_then(|args| args.get_dict_value(name).ok().flatten()).and_then(|v| v.as_int().ok());
+    let page = int_arg("page").unwrap_or(0).max(0) as usize;
+
+    match func {
+        "ping" => BValue::builder().set_dict().add_dict_entry("q", |b| b.set_str("pong".to_string())).build(),
+
+        "cookie" => {
+            state.next_cookie += 1;
+            let cookie = state.next_cookie.to_string();
+            state.cookies.insert(cookie.clone());
+            BValue::builder().set_dict().add_dict_entry("cookie", |b| b.set_str(cookie)).build()
+        }
+
+        "Admin_availableFunctions" => {
+            let page_fns = FUNCTIONS.iter().skip(page * FUNCTIONS_PAGE_SIZE).take(FUNCTIONS_PAGE_SIZE);
+            let fns = page_fns.fold(BValue::builder().set_dict(), |fns, &(name, args)| {
+                fns.add_dict_entry(name, |b| {
+                    args.iter().fold(b.set_dict(), |b, &(arg_name, required, typ)| {
+                        b.add_dict_entry(arg_name, |b| {
+                            b.set_dict()
+                                .add_dict_entry("required", |b| b.set_int(required as i64))
+                                .add_dict_entry("type", |b| b.set_str(typ.to_string()))
+                        })
+                    })
+                })
+            });
+            let more = FUNCTIONS.len() > (page + 1) * FUNCTIONS_PAGE_SIZE;
+            BValue::builder()
+                .set_dict()
+                .add_dict_entry("availableFunctions", |_| fns)
+                .add_dict_entry("more", |b| b.set_int(more as i64))
+                .build()
+        }
+
+        "AuthorizedPasswords_list" => ok_reply(BValue::builder().set_dict().add_dict_entry("users", |b| b.set_list()).build()),
+
+        "Core_nodeInfo" => {
+            let forms = config.encoding_scheme.iter().fold(BValue::builder().set_list(), |list, form| {
+                list.add_list_item(|b| {
+                    b.set_dict()
+                        .add_dict_entry("bitCount", |b| b.set_int(form.bit_count as i64))
+                        .add_dict_entry("prefix", |b| b.set_str(form.prefix.clone()))
+                        .add_dict_entry("prefixLen", |b| b.set_int(form.prefix_len as i64))
+                })
+            });
+            let reply = BValue::builder()
+                .set_dict()
+                .add_dict_entry("myAddr", |b| b.set_str(config.my_addr.clone()))
+                .add_dict_entry("encodingScheme", |_| forms)
+                .build();
+            ok_reply(reply)
+        }
+
+        "UpperDistributor_registerHandler" => match (int_arg("contentType"), int_arg("udpPort")) {
+            (Some(content_type), Some(port)) if content_type >= 0 && content_type <= u32::MAX as i64 && port > 0 && port <= u16::MAX as i64 => {
+                state.handlers.push((content_type as u32, port as u16));
+                ok_reply(BValue::builder().set_dict().build())
+            }
+            _ => error_reply("invalid args"),
+        },
+
+        "UpperDistributor_listHandlers" => {
+            let handlers = state.handlers.iter().skip(page * HANDLERS_PAGE_SIZE).take(HANDLERS_PAGE_SIZE);
+            let list = handlers.fold(BValue::builder().set_list(), |list, &(content_type, port)| {
+                list.add_list_item(|b| {
+                    b.set_dict()
+                        .add_dict_entry("type", |b| b.set_int(content_type as i64))
+                        .add_dict_entry("udpPort", |b| b.set_int(port as i64))
+                })
+            });
+            ok_reply(BValue::builder().set_dict().add_dict_entry("handlers", |_| list).build())
+        }
+
+        "UpperDistributor_unregisterHandler" => {
+            let port = int_arg("udpPort");
+            let count = state.handlers.len();
+            state.handlers.retain(|&(_, handler_port)| Some(handler_port as i64) != port);
+            if state.handlers.len() < count {
+                ok_reply(BValue::builder().set_dict().build())
+            } else {
+                error_reply("handler not found")
+            }
+        }
+
+        _ => error_reply("No such function"),
+    }
+}
+
+fn ok_reply(mut reply: BValue) -> BValue {
+    reply.set_dict_value("error", BValue::builder().set_str("none".to_string()).build()).expect("dict reply");
+    reply
+}
+
+fn error_reply(error: &str) -> BValue {
+    BValue::builder().set_dict().add_dict_entry("error", |b| b.set_str(error.to_string())).build()
+}
+
+#[tokio::test]
+async fn test_mock_router() {
+    use crate::Error;
+
+    let mut router = MockRouter::start(MockConfig::default()).await.expect("start mock");
+    let mut conn = crate::connect(Some(router.opts())).await.expect("connect");
+
+    // Function list spans two pages, the third one is empty
+    assert!(FUNCTIONS.len() > FUNCTIONS_PAGE_SIZE && FUNCTIONS.len() <= 2 * FUNCTIONS_PAGE_SIZE);
+    assert_eq!(conn.functions.iter().count(), FUNCTIONS.len());
+    assert_eq!(router.calls().iter().filter(|&f| f == "Admin_availableFunctions").count(), 3);
+
+    let res = crate::cjdns_invoke!(conn, "Core_nodeInfo").await.expect("Core_nodeInfo");
+    assert_eq!(res["myAddr"].as_str().expect("myAddr"), MockConfig::default().my_addr);
+
+    let (content_type, port) = (256, 1234);
+    crate::cjdns_invoke!(conn, "UpperDistributor_registerHandler", "contentType" = content_type as i64, "udpPort" = port as i64)
+        .await
+        .expect("registerHandler");
+    assert_eq!(router.handlers(), vec![(content_type, port)]);
+    let res = crate::cjdns_invoke!(conn, "UpperDistributor_unregisterHandler", "udpPort" = port as i64 + 1).await;
+    assert!(matches!(res, Err(Error::RemoteError(_))));
+    crate::cjdns_invoke!(conn, "UpperDistributor_unregisterHandler", "udpPort" = port as i64)
+        .await
+        .expect("unregisterHandler");
+    assert!(router.handlers().is_empty());
+
+    // Handler traffic goes both ways
+    let handler = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await.expect("bind");
+    let port = handler.local_addr().expect("local addr").port();
+    let (mut handler_recv, mut handler_send) = handler.split();
+    router.state.lock().unwrap().handlers.push((content_type, port));
+    assert_eq!(router.inject(content_type, b"packet").await.expect("inject"), 1);
+    assert_eq!(router.inject(1, b"other").await.expect("inject"), 0);
+    let mut buf = [0; 16];
+    let (size, _) = handler_recv.recv_from(&mut buf).await.expect("recv");
+    assert_eq!(&buf[..size], b"packet");
+    handler_send.send_to(b"reply", &router.inject_addr()).await.expect("send");
+    assert_eq!(router.capture().await.expect("capture"), b"reply");
+
+    // Wrong password
+    let opts = Opts {
+        password: Some("wrong".to_string()),
+        ..router.opts()
+    };
+    assert!(matches!(crate::connect(Some(opts)).await, Err(Error::AuthError(_))));
+}
@@ -360,3 +360,46 @@ pub enum ReceiveError {
     #[error("Data parse error: {0}")]
     ParseError(#[source] ParseError, Vec<u8>),
 }
+
+#[tokio::test]
+async fn test_sniff_mock_router() {
+    use cjdns_admin::mock::{MockConfig, MockRouter};
+
+    let mut router = MockRouter::start(MockConfig::default()).await.expect("start mock router");
+    let cjdns = cjdns_admin::connect(Some(router.opts())).await.expect("connect");
+    let mut sniffer = Sniffer::sniff_traffic(cjdns, ContentType::Cjdht).await.expect("sniff");
+    let handlers = router.handlers();
+    assert_eq!(handlers.len(), 1);
+    assert_eq!(handlers[0].0, ContentType::Cjdht as u32);
+
+    // Incoming DHT message: route header, data header and bencoded content
+    let mut packet = hex::decode(
+        "a331ebbed8d92ac03b10efed3e389cd0c6ec7331a72dbde198476c5eb4d14a1f0000000000000013004800000000000001000000fc928136dc1fe6e04ef6a6dd7187b85f",
+    )
+    .expect("bad route header");
+    let data_header = DataHeader {
+        content_type: ContentType::Cjdht,
+        ..DataHeader::default()
+    };
+    packet.extend(data_header.serialize().expect("data header"));
+    let content = BValue::builder()
+        .set_dict()
+        .add_dict_entry("sq", |b| b.set_str("pn".to_string()))
+        .add_dict_entry("txid", |b| b.set_bytes(vec![1, 2, 3]))
+        .build();
+    packet.extend(content.encode().expect("content"));
+    assert_eq!(router.inject(ContentType::Cjdht as u32, &packet).await.expect("inject"), 1);
+
+    let msg = sniffer.receive().await.expect("receive");
+    assert_eq!(msg.content_type, ContentType::Cjdht);
+    assert!(matches!(&msg.content, Content::Benc(benc) if *benc == content));
+    assert_eq!(msg.raw_bytes.as_deref(), Some(&packet[..]));
+
+    // Sent message reaches the router unchanged
+    let dest = router.inject_addr().to_string();
+    sniffer.send(Message { raw_bytes: None, ..msg }, Some(&dest)).await.expect("send");
+    assert_eq!(router.capture().await.expect("capture"), packet);
+
+    sniffer.disconnect().await.expect("disconnect");
+    assert!(router.handlers().is_empty());
+}
@@ -11,6 +11,8 @@
 //! * Build: `$ cargo build --release`
 //! * Create the config file: `$ cp config.example.json ./config.json`
 //! * Start the node: `$ ../target/release/cjdns-snode`
+//! * Replay a dump offline, e.g. to reproduce a routing issue: `$ ../target/release/cjdns-snode replay ./dump.bin`
+//! * Stop it with Ctrl+C or SIGTERM: it unregisters from the local node, closes the peer connections and saves the state file (if configured) before exiting
 
 #[macro_use]
 extern crate anyhow;
@@ -43,7 +45,17 @@ async fn run() -> Result<()> {
     debug!("{:?}", config);
 
     // Run the application
-    server::main(config).await
+    match opts.command {
+        Some(args::Command::Replay(replay)) => {
+            let options = server::ReplayOptions {
+                sample_routes: replay.routes,
+                json: replay.json,
+                output: replay.output,
+            };
+            server::replay(opts.config_file, config, &replay.dump_file, options).await
+        }
+        None => server::main(opts.config_file, config, opts.bootstrap_from).await,
+    }
 }
 
 /// Logger initialization
@@ -94,33 +106,289 @@ mod args {
         /// Config file path
         #[clap(long = "config", default_value = "./config.json")]
         pub config_file: PathBuf,
+
+        /// Bootstrap node table from another supernode's `/dump` URL or a dump file
+        #[clap(long = "bootstrap-from")]
+        pub bootstrap_from: Option<String>,
+
+        #[clap(subcommand)]
+        pub command: Option<Command>,
+    }
+
+    #[derive(Clap)]
+    pub enum Command {
+        /// Replay announcements from a dump file offline and print the resulting node table
+        #[clap(name = "replay")]
+        Replay(Replay),
+    }
+
+    #[derive(Clap)]
+    pub struct Replay {
+        /// Dump file, as produced by the `/dump` endpoint or the state saving
+        pub dump_file: PathBuf,
+
+        /// Number of node pairs to report routes between
+        #[clap(long = "routes", default_value = "10")]
+        pub routes: usize,
+
+        /// Write the report as JSON
+        #[clap(long = "json")]
+        pub json: bool,
+
+        /// Write the report to the file instead of stdout
+        #[clap(long = "output")]
+        pub output: Option<PathBuf>,
     }
 }
 
 /// Config file parsing.
 mod config {
-    use std::path::Path;
+    use std::net::SocketAddr;
+    use std::path::{Path, PathBuf};
 
     use anyhow::Error;
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use tokio::fs;
 
+    use crate::utils::ip6_prefix::Ip6Prefix;
+
     /// Load config file
     pub(super) async fn load(file_path: &Path) -> Result<Config, Error> {
         let json = fs::read(file_path)
             .await
             .map_err(|e| anyhow!("failed to load config file '{}': {}", file_path.display(), e))?;
-        let config = serde_json::from_slice(&json).map_err(|e| anyhow!("failed to parse config file '{}': {}", file_path.display(), e))?;
+        let config: Config = serde_json::from_slice(&json).map_err(|e| anyhow!("failed to parse config file '{}': {}", file_path.display(), e))?;
+        if config.admin_token.is_some() {
+            warn!("Config option 'adminToken' is deprecated, use an 'auth.tokens' entry with the 'admin' scope instead");
+        }
         Ok(config)
     }
 
-    #[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize)]
+    #[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
     pub struct Config {
         #[serde(rename = "connectCjdns")]
         pub connect: bool,
 
         #[serde(rename = "peers")]
         pub peers: Vec<String>,
+
+        /// File to save the node table to and restore it from on startup (disabled if not set)
+        #[serde(rename = "stateFile", default)]
+        pub state_file: Option<PathBuf>,
+
+        /// How often to save the node table, seconds
+        #[serde(rename = "stateSaveInterval", default = "default_state_save_interval")]
+        pub state_save_interval: u64,
+
+        /// Deprecated: use an `auth.tokens` entry with the `admin` scope instead. Still accepted as an admin token.
+        #[serde(rename = "adminToken", default, skip_serializing)]
+        pub admin_token: Option<String>,
+
+        /// Access tokens for the HTTP/WebSocket routes (only the admin routes are protected if not set),
+        /// e.g. `"auth": { "tokens": [{ "token": "<long random string>", "scopes": ["admin", "read"] }] }`
+        #[serde(rename = "auth", default)]
+        pub auth: Option<AuthConfig>,
+
+        /// HTTP/WebSocket listeners, each serving its own set of routes
+        #[serde(rename = "listeners", default = "default_listeners")]
+        pub listeners: Vec<ListenerConfig>,
+
+        /// Limits on announcements received from the local node (no limits if not set)
+        #[serde(rename = "annRateLimit", default)]
+        pub ann_rate_limit: Option<RateLimitConfig>,
+
+        /// Which nodes' announcements, and links to which nodes, are accepted (all nodes if not set),
+        /// e.g. `"annPolicy": { "allowPrefixes": ["fc00::/8"], "minVersion": 20 }`
+        #[serde(rename = "annPolicy", default)]
+        pub ann_policy: Option<AnnPolicyConfig>,
+
+        /// Maximum number of alternative routes returned for a single route query
+        #[serde(rename = "maxRoutes", default = "default_max_routes")]
+        pub max_routes: usize,
+
+        /// Link cost model used for route queries which don't specify one
+        #[serde(rename = "costModel", default)]
+        pub cost_model: CostModel,
+
+        /// Path search algorithm used to find routes
+        #[serde(rename = "pathSolver", default)]
+        pub path_solver: PathSolver,
+
+        /// How long to keep link state history, seconds (never less than 20 minutes, which routing relies upon)
+        #[serde(rename = "linkStateRetention", default = "default_link_state_retention")]
+        pub link_state_retention: u64,
+
+        /// Nodes below this protocol version are reported as outdated in the version census (none if not set)
+        #[serde(rename = "minNodeVersion", default)]
+        pub min_node_version: Option<u16>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct ListenerConfig {
+        /// Address and port to listen on
+        #[serde(rename = "addr")]
+        pub addr: SocketAddr,
+
+        /// Route sets served by this listener
+        #[serde(rename = "routes")]
+        pub routes: Vec<RouteSet>,
+    }
+
+    /// Set of web server routes which can be enabled for a listener.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub enum RouteSet {
+        /// Peer supernodes WebSocket endpoint
+        #[serde(rename = "peer")]
+        Peer,
+
+        /// Read-only query endpoints
+        #[serde(rename = "query")]
+        Query,
+
+        /// Endpoints which change server state
+        #[serde(rename = "admin")]
+        Admin,
+    }
+
+    /// Built-in link cost models used for routing.
+    #[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+    pub enum CostModel {
+        /// Reciprocal of the decayed link state value, which accounts for traffic, latency and drops
+        #[default]
+        #[serde(rename = "value")]
+        Value,
+
+        /// Average latency of the link
+        #[serde(rename = "latency")]
+        Latency,
+
+        /// Every link costs the same, so the route with the fewest hops wins
+        #[serde(rename = "hopCount")]
+        HopCount,
+
+        /// Links with packet drops are heavily penalized, otherwise the fewest hops win
+        #[serde(rename = "dropAverse")]
+        DropAverse,
+    }
+
+    /// Path search algorithms used for routing.
+    #[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub enum PathSolver {
+        /// Dijkstra search from one end
+        #[default]
+        #[serde(rename = "dijkstra")]
+        Dijkstra,
+
+        /// Dijkstra search from both ends at once, faster on large meshes
+        #[serde(rename = "bidirectional")]
+        Bidirectional,
+    }
+
+    /// Bearer tokens granting access to route scopes. The admin scope is always protected,
+    /// the read and peer scopes only once some token (or the peer secret) grants them.
+    #[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct AuthConfig {
+        /// Tokens accepted in the `Authorization: Bearer <token>` request header
+        #[serde(rename = "tokens", default, skip_serializing)]
+        pub tokens: Vec<AuthTokenConfig>,
+
+        /// Secret shared by peer supernodes: presented when connecting to the peers, and accepted as a `peer` token.
+        /// It is sent as is, so only set it if all the peers are reached over `wss://` or a trusted network.
+        #[serde(rename = "peerSecret", default, skip_serializing)]
+        pub peer_secret: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct AuthTokenConfig {
+        #[serde(rename = "token")]
+        pub token: String,
+
+        /// Scopes the token grants access to
+        #[serde(rename = "scopes")]
+        pub scopes: Vec<AuthScope>,
+    }
+
+    /// Access scope of the web server routes.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub enum AuthScope {
+        /// Endpoints which change server state
+        #[serde(rename = "admin")]
+        Admin,
+
+        /// Read-only query endpoints
+        #[serde(rename = "read")]
+        Read,
+
+        /// Peer supernodes WebSocket endpoint
+        #[serde(rename = "peer")]
+        Peer,
+    }
+
+    #[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct RateLimitConfig {
+        /// Limit for each announcing node
+        #[serde(rename = "perNode", default)]
+        pub per_node: Option<RateBucketConfig>,
+
+        /// Limit for all nodes together
+        #[serde(rename = "global", default)]
+        pub global: Option<RateBucketConfig>,
+    }
+
+    /// Token bucket parameters.
+    #[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct RateBucketConfig {
+        /// Sustained rate, announcements per minute
+        #[serde(rename = "perMinute")]
+        pub per_minute: u32,
+
+        /// Maximum number of announcements accepted in a burst
+        #[serde(rename = "burst")]
+        pub burst: u32,
+    }
+
+    /// Node allow/deny rules. A node is accepted if it is not denied by any rule,
+    /// and, when any allow rules are present, is allowed by at least one of them.
+    #[derive(Clone, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub struct AnnPolicyConfig {
+        /// Public keys of the nodes to accept, like `xxx.k`
+        #[serde(rename = "allowKeys", default)]
+        pub allow_keys: Vec<String>,
+
+        /// Public keys of the nodes to refuse
+        #[serde(rename = "denyKeys", default)]
+        pub deny_keys: Vec<String>,
+
+        /// IPv6 prefixes of the nodes to accept, like `fc00::/8`
+        #[serde(rename = "allowPrefixes", default)]
+        pub allow_prefixes: Vec<Ip6Prefix>,
+
+        /// IPv6 prefixes of the nodes to refuse
+        #[serde(rename = "denyPrefixes", default)]
+        pub deny_prefixes: Vec<Ip6Prefix>,
+
+        /// Minimum accepted node protocol version
+        #[serde(rename = "minVersion", default)]
+        pub min_version: Option<u16>,
+    }
+
+    fn default_state_save_interval() -> u64 {
+        5 * 60
+    }
+
+    fn default_max_routes() -> usize {
+        1
+    }
+
+    fn default_link_state_retention() -> u64 {
+        20 * 60
+    }
+
+    fn default_listeners() -> Vec<ListenerConfig> {
+        vec![ListenerConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 3333)),
+            routes: vec![RouteSet::Peer, RouteSet::Query, RouteSet::Admin],
+        }]
     }
 }
 
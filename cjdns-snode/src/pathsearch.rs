@@ -1,16 +1,25 @@
 //! Path search in a weighted graph.
 
+pub use self::bidirectional::BidirectionalDijkstra;
 pub use self::dijkstra::Dijkstra;
-pub use self::graph::{GraphBuilder, GraphSolver};
+pub use self::graph::{GraphBuilder, GraphSolver, LinkFilter, PathConstraints, PathSearchTree};
 
+mod bidirectional;
 mod dijkstra;
 mod frontier;
 mod graph;
 mod numtraits;
+mod yen;
 
-#[test]
-fn test_dijkstra_search() {
-    let mut g = Dijkstra::new();
+/// Graph solver implementations are tested with the same checks, as they must give the same results.
+#[cfg(test)]
+trait TestGraph: GraphBuilder<&'static str, f64> + GraphSolver<&'static str, f64> {}
+
+#[cfg(test)]
+impl<G: GraphBuilder<&'static str, f64> + GraphSolver<&'static str, f64>> TestGraph for G {}
+
+#[cfg(test)]
+fn check_dijkstra_search<G: TestGraph>(mut g: G) {
     g.add_node("A", vec![("B", 1.0)]);
     g.add_node("B", vec![("A", 1.0), ("C", 2.0), ("D", 4.0)]);
     g.add_node("C", vec![("B", 2.0), ("D", 1.0)]);
@@ -20,8 +29,13 @@ fn test_dijkstra_search() {
 }
 
 #[test]
-fn test_dijkstra_search_all() {
-    let mut g = Dijkstra::new();
+fn test_dijkstra_search() {
+    check_dijkstra_search(Dijkstra::new());
+    check_dijkstra_search(BidirectionalDijkstra::new());
+}
+
+#[cfg(test)]
+fn check_dijkstra_search_all<G: TestGraph>(mut g: G) {
     g.add_node("1", vec![("2", 7.0), ("3", 9.0), ("6", 14.0)]);
     g.add_node("2", vec![("1", 7.0), ("3", 10.0), ("4", 15.0)]);
     g.add_node("3", vec![("1", 9.0), ("2", 10.0), ("4", 11.0), ("6", 2.0)]);
@@ -36,3 +50,137 @@ fn test_dijkstra_search_all() {
         vec![("2", vec![]), ("3", vec![]), ("4", vec!["3"]), ("5", vec!["3", "6"]), ("6", vec!["3"]),],
     );
 }
+
+#[test]
+fn test_dijkstra_search_all() {
+    check_dijkstra_search_all(Dijkstra::new());
+    check_dijkstra_search_all(BidirectionalDijkstra::new());
+}
+
+#[cfg(test)]
+fn check_dijkstra_update<G: TestGraph>(mut g: G) {
+    g.add_node("A", vec![("B", 1.0), ("C", 5.0)]);
+    g.add_node("B", vec![("A", 1.0), ("C", 1.0)]);
+    g.add_node("C", vec![("A", 5.0), ("B", 1.0)]);
+    assert_eq!(g.path(&"A", &"C"), vec!["A", "B", "C"]);
+    assert_eq!(g.links(&"B"), Some(&[("A", 1.0), ("C", 1.0)][..]));
+
+    // Make the link through B expensive
+    g.add_node("B", vec![("A", 1.0), ("C", 10.0)]);
+    assert_eq!(g.path(&"A", &"C"), vec!["A", "C"]);
+
+    // Remove C and its links
+    g.remove_node(&"C");
+    g.add_node("A", vec![("B", 1.0)]);
+    g.add_node("B", vec![("A", 1.0)]);
+    assert!(g.links(&"C").is_none());
+    assert_eq!(g.path(&"A", &"C"), Vec::<&str>::new());
+}
+
+#[test]
+fn test_dijkstra_update() {
+    check_dijkstra_update(Dijkstra::new());
+    check_dijkstra_update(BidirectionalDijkstra::new());
+}
+
+#[cfg(test)]
+fn check_k_shortest_paths<G: TestGraph>(mut g: G) {
+    // Classic example from the Wikipedia article on Yen's algorithm
+    g.add_node("C", vec![("D", 3.0), ("E", 2.0)]);
+    g.add_node("D", vec![("F", 4.0)]);
+    g.add_node("E", vec![("D", 1.0), ("F", 2.0), ("G", 3.0)]);
+    g.add_node("F", vec![("G", 2.0), ("H", 1.0)]);
+    g.add_node("G", vec![("H", 2.0)]);
+    g.add_node("H", vec![]);
+    let no_constraints = PathConstraints::default();
+    assert_eq!(
+        g.k_shortest_paths(&"C", &"H", 3, &no_constraints),
+        vec![vec!["C", "E", "F", "H"], vec!["C", "E", "G", "H"], vec!["C", "D", "F", "H"]],
+    );
+    assert_eq!(g.k_shortest_paths(&"C", &"H", 1, &no_constraints), vec![g.path(&"C", &"H")]);
+    assert_eq!(g.k_shortest_paths(&"C", &"H", 100, &no_constraints).len(), 7);
+    assert!(g.k_shortest_paths(&"H", &"C", 3, &no_constraints).is_empty());
+    assert!(g.k_shortest_paths(&"C", &"H", 0, &no_constraints).is_empty());
+}
+
+#[test]
+fn test_k_shortest_paths() {
+    check_k_shortest_paths(Dijkstra::new());
+    check_k_shortest_paths(BidirectionalDijkstra::new());
+}
+
+#[cfg(test)]
+fn check_constrained_paths<G: TestGraph>(mut g: G) {
+    g.add_node("A", vec![("B", 1.0), ("E", 10.0)]);
+    g.add_node("B", vec![("C", 1.0)]);
+    g.add_node("C", vec![("D", 1.0)]);
+    g.add_node("D", vec![("F", 1.0)]);
+    g.add_node("E", vec![("F", 10.0)]);
+    g.add_node("F", vec![]);
+
+    let shortest = |constraints: &PathConstraints<&'static str>| g.k_shortest_paths(&"A", &"F", 1, constraints);
+    assert_eq!(shortest(&PathConstraints::default()), vec![vec!["A", "B", "C", "D", "F"]]);
+
+    let avoid_c = PathConstraints {
+        excluded_nodes: vec!["C"].into_iter().collect(),
+        ..PathConstraints::default()
+    };
+    assert_eq!(shortest(&avoid_c), vec![vec!["A", "E", "F"]]);
+
+    let max_3_hops = PathConstraints {
+        max_hops: Some(3),
+        ..PathConstraints::default()
+    };
+    assert_eq!(shortest(&max_3_hops), vec![vec!["A", "E", "F"]]);
+    let max_1_hop = PathConstraints {
+        max_hops: Some(1),
+        ..PathConstraints::default()
+    };
+    assert!(shortest(&max_1_hop).is_empty());
+
+    let no_link_e_f = |a: &&str, b: &&str| !(*a == "E" && *b == "F");
+    let filtered = PathConstraints {
+        link_filter: Some(&no_link_e_f),
+        ..PathConstraints::default()
+    };
+    assert_eq!(g.k_shortest_paths(&"A", &"F", 2, &filtered), vec![vec!["A", "B", "C", "D", "F"]]);
+    assert_eq!(g.k_shortest_paths(&"A", &"F", 2, &PathConstraints::default()).len(), 2);
+}
+
+#[test]
+fn test_constrained_paths() {
+    check_constrained_paths(Dijkstra::new());
+    check_constrained_paths(BidirectionalDijkstra::new());
+}
+
+#[test]
+fn test_bidirectional_same_costs() {
+    // Pseudo-random sparse graph, including nodes unreachable from some others
+    let tags = (0..60).map(|i| &*Box::leak(format!("N{}", i).into_boxed_str())).collect::<Vec<&'static str>>();
+    let mut rnd = 12345_u64;
+    let mut next = |n: u64| {
+        rnd = rnd.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (rnd >> 33) % n
+    };
+    let mut d = Dijkstra::new();
+    let mut b = BidirectionalDijkstra::new();
+    for &tag in tags.iter() {
+        let links = (0..next(4)).map(|_| (tags[next(60) as usize], (next(10) + 1) as f64)).filter(|&(peer, _)| peer != tag).collect::<Vec<_>>();
+        d.add_node(tag, links.clone());
+        b.add_node(tag, links);
+    }
+
+    let cost = |path: &[&'static str]| {
+        path.windows(2)
+            .map(|pair| d.links(&pair[0]).unwrap().iter().filter(|(tag, _)| *tag == pair[1]).map(|&(_, w)| w).fold(f64::INFINITY, f64::min))
+            .sum::<f64>()
+    };
+    for &from in tags.iter() {
+        for &to in tags.iter() {
+            let expected = d.path(&from, &to);
+            let actual = b.path(&from, &to);
+            assert_eq!(expected.is_empty(), actual.is_empty(), "{} -> {}", from, to);
+            assert_eq!(cost(&expected), cost(&actual), "{} -> {}", from, to);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Bidirectional Dijkstra path search implementation.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::ops::Add;
+
+use super::dijkstra::Dijkstra;
+use super::frontier::Frontier;
+use super::graph::{GraphBuilder, GraphSolver, PathConstraints, PathSearchTree};
+use super::numtraits::{IntoOrd, Zero};
+use super::yen::{self, ShortestPath};
+
+/// Bidirectional Dijkstra path search.
+/// Explores the graph from both ends at once, which visits much fewer nodes on large graphs.
+/// Searches with a hop limit and path search trees are done from one end only.
+pub struct BidirectionalDijkstra<T, W> {
+    /// Graph with outgoing links of each node, also used for single-ended searches.
+    forward: Dijkstra<T, W>,
+
+    /// Links node with tag `<T>` to the list of nodes having links to it, with corresponding weights `<W>`.
+    backward: HashMap<T, Vec<(T, W)>>,
+}
+
+impl<T, W> BidirectionalDijkstra<T, W> {
+    /// Create new instance with empty graph.
+    pub fn new() -> Self {
+        BidirectionalDijkstra {
+            forward: Dijkstra::new(),
+            backward: HashMap::new(),
+        }
+    }
+}
+
+impl<T, W> Default for BidirectionalDijkstra<T, W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, W> BidirectionalDijkstra<T, W>
+where
+    T: Clone + Eq + Hash,
+    W: PartialEq + PartialOrd + Zero,
+{
+    /// Remove incoming links from the node to its peers.
+    fn remove_backward_links(&mut self, node_tag: &T) {
+        let peers = match self.forward.links(node_tag) {
+            Some(links) => links.iter().map(|(peer, _)| peer.clone()).collect::<Vec<_>>(),
+            None => return,
+        };
+        for peer in peers {
+            if let Some(incoming) = self.backward.get_mut(&peer) {
+                incoming.retain(|(tag, _)| tag != node_tag);
+                if incoming.is_empty() {
+                    self.backward.remove(&peer);
+                }
+            }
+        }
+    }
+}
+
+impl<T, W> GraphBuilder<T, W> for BidirectionalDijkstra<T, W>
+where
+    T: Clone + Eq + Hash,
+    W: Clone + PartialEq + PartialOrd + Zero,
+{
+    fn add_node<I: IntoIterator<Item = (T, W)>>(&mut self, node_tag: T, links: I) {
+        self.remove_backward_links(&node_tag);
+        let links = links.into_iter().collect::<Vec<(T, W)>>();
+        for (peer, weight) in links.iter() {
+            self.backward.entry(peer.clone()).or_default().push((node_tag.clone(), weight.clone()));
+        }
+        self.forward.add_node(node_tag, links);
+    }
+
+    fn remove_node(&mut self, node_tag: &T) {
+        self.remove_backward_links(node_tag);
+        self.forward.remove_node(node_tag);
+    }
+
+    fn links(&self, node_tag: &T) -> Option<&[(T, W)]> {
+        self.forward.links(node_tag)
+    }
+}
+
+/// State of the search from one of the ends.
+struct SearchSide<T, W: IntoOrd> {
+    explored: HashSet<T>,
+    frontier: Frontier<T, W>,
+    /// Best known cost from this side's end to each reached node.
+    costs: HashMap<T, W>,
+    /// Previous node on the best known path from this side's end to each reached node.
+    previous: HashMap<T, T>,
+}
+
+impl<T, W> SearchSide<T, W>
+where
+    T: Clone + Eq + Ord + Hash,
+    W: Clone + PartialOrd + IntoOrd + Zero,
+{
+    fn new(start: &T) -> Self {
+        let mut side = SearchSide {
+            explored: HashSet::new(),
+            frontier: Frontier::new(),
+            costs: HashMap::new(),
+            previous: HashMap::new(),
+        };
+        side.frontier.push(start.clone(), W::ZERO);
+        side.costs.insert(start.clone(), W::ZERO);
+        side
+    }
+
+    /// Nodes from the given one back to this side's end, inclusive.
+    fn path_to_end(&self, node: &T) -> Vec<T> {
+        let mut path = vec![node.clone()];
+        let mut cur = node;
+        while let Some(prev) = self.previous.get(cur) {
+            path.push(prev.clone());
+            cur = prev;
+        }
+        path
+    }
+}
+
+impl<T, W> BidirectionalDijkstra<T, W>
+where
+    T: Clone + Eq + Ord + Hash,
+    W: Clone + PartialEq + PartialOrd + IntoOrd + Add<Output = W> + Zero,
+{
+    /// Find a reverse path from `to` node to `from` node together with its cost,
+    /// searching from both ends until the frontiers can no longer produce a cheaper path.
+    fn bidirectional_search(
+        &self,
+        from: &T,
+        to: &T,
+        constraints: &PathConstraints<T>,
+        excluded_nodes: &HashSet<T>,
+        excluded_links: &HashSet<(T, T)>,
+    ) -> Option<(Vec<T>, W)> {
+        // Same as the single-ended search, which never reaches the excluded destination
+        // and finds no path from a node to itself
+        if from == to || constraints.excluded_nodes.contains(to) || excluded_nodes.contains(to) {
+            return None;
+        }
+
+        // Whether the node can be entered by the search, the starting node is never excluded
+        let node_allowed = |tag: &T| tag == from || !(constraints.excluded_nodes.contains(tag) || excluded_nodes.contains(tag));
+
+        // Whether the link `a -> b` can be used
+        let link_allowed = |a: &T, b: &T| {
+            if !excluded_links.is_empty() && excluded_links.contains(&(a.clone(), b.clone())) {
+                return false;
+            }
+            constraints.link_filter.map(|link_filter| link_filter(a, b)).unwrap_or(true)
+        };
+
+        let mut fwd = SearchSide::<T, W>::new(from);
+        let mut bwd = SearchSide::<T, W>::new(to);
+
+        // Cheapest path found so far, as its cost and the node where both searches met
+        let mut best: Option<(W, T)> = None;
+
+        // Run until either side has visited every node in its frontier
+        while let (Some(fwd_cost), Some(bwd_cost)) = (fwd.frontier.peek_cost(), bwd.frontier.peek_cost()) {
+            let (fwd_cost, bwd_cost) = (fwd_cost.clone(), bwd_cost.clone());
+
+            // Any path not found yet costs at least the sum of both frontiers' minimums
+            if let Some((best_cost, _)) = &best {
+                if (fwd_cost.clone() + bwd_cost.clone()).into_ord() >= best_cost.clone().into_ord() {
+                    break;
+                }
+            }
+
+            // Expand the side with the cheaper frontier
+            let forward = fwd_cost.into_ord() <= bwd_cost.into_ord();
+            let (side, other) = if forward { (&mut fwd, &bwd) } else { (&mut bwd, &fwd) };
+
+            let (tag, cost) = side.frontier.pop().expect("frontier is empty");
+            side.explored.insert(tag.clone());
+
+            let neighbors = if forward { self.forward.links(&tag) } else { self.backward.get(&tag).map(|links| links.as_slice()) };
+            for (n_tag, n_cost) in neighbors.into_iter().flatten() {
+                // If we already explored the node - skip it
+                if side.explored.contains(n_tag) {
+                    continue;
+                }
+
+                // Skip excluded nodes and links, backward search goes along the links in reverse direction
+                if !node_allowed(n_tag) {
+                    continue;
+                }
+                let link_usable = if forward { link_allowed(&tag, n_tag) } else { link_allowed(n_tag, &tag) };
+                if !link_usable {
+                    continue;
+                }
+
+                let node_cost = cost.clone() + n_cost.clone();
+                if side.frontier.try_insert_or_decrease_cost(n_tag, node_cost.clone()) {
+                    side.previous.insert(n_tag.clone(), tag.clone());
+                    side.costs.insert(n_tag.clone(), node_cost.clone());
+
+                    // The node is reached from both ends, so there is a path through it
+                    if let Some(other_cost) = other.costs.get(n_tag) {
+                        let path_cost = node_cost + other_cost.clone();
+                        let better = match &best {
+                            Some((best_cost, _)) => path_cost.clone().into_ord() < best_cost.clone().into_ord(),
+                            None => true,
+                        };
+                        if better {
+                            best = Some((path_cost, n_tag.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        let (_, meeting) = best?;
+        let path_cost = fwd.costs[&meeting].clone() + bwd.costs[&meeting].clone();
+
+        // Path from the meeting node to `to` is the beginning of the reverse path,
+        // and the reversed path from `from` to the meeting node is the rest of it.
+        let mut rev_path = bwd.path_to_end(&meeting);
+        rev_path.reverse();
+        rev_path.pop();
+        rev_path.extend(fwd.path_to_end(&meeting));
+
+        Some((rev_path, path_cost))
+    }
+}
+
+impl<T, W> ShortestPath<T, W> for BidirectionalDijkstra<T, W>
+where
+    T: Clone + Eq + Ord + Hash,
+    W: Clone + PartialEq + PartialOrd + IntoOrd + Add<Output = W> + Zero,
+{
+    fn search(
+        &self,
+        from: &T,
+        to: &T,
+        constraints: &PathConstraints<T>,
+        excluded_nodes: &HashSet<T>,
+        excluded_links: &HashSet<(T, T)>,
+        max_hops: Option<usize>,
+    ) -> Option<(Vec<T>, W)> {
+        // Hop limit needs tracking of hops per node, so the plain search is used
+        if max_hops.is_some() {
+            self.forward.search(from, to, constraints, excluded_nodes, excluded_links, max_hops)
+        } else {
+            self.bidirectional_search(from, to, constraints, excluded_nodes, excluded_links)
+        }
+    }
+
+    fn path_cost(&self, path: &[T]) -> Option<W> {
+        self.forward.path_cost(path)
+    }
+}
+
+impl<T, W> GraphSolver<T, W> for BidirectionalDijkstra<T, W>
+where
+    T: Clone + Eq + Ord + Hash,
+    W: Clone + PartialEq + PartialOrd + IntoOrd + Add<Output = W> + Zero,
+{
+    fn path(&self, from: &T, to: &T) -> Vec<T> {
+        let mut path = self.reverse_path(from, to);
+
+        // Reverse the path, so the result will be from `from` to `to`
+        path.reverse();
+
+        path
+    }
+
+    fn reverse_path(&self, from: &T, to: &T) -> Vec<T> {
+        self.bidirectional_search(from, to, &PathConstraints::default(), &HashSet::new(), &HashSet::new())
+            .map(|(rev_path, _)| rev_path)
+            .unwrap_or_default()
+    }
+
+    fn k_shortest_paths(&self, from: &T, to: &T, k: usize, constraints: &PathConstraints<T>) -> Vec<Vec<T>> {
+        yen::k_shortest_paths(self, from, to, k, constraints)
+    }
+
+    fn path_search_tree(&self, start: &T) -> PathSearchTree<T> {
+        self.forward.path_search_tree(start)
+    }
+}
@@ -5,8 +5,9 @@ use std::hash::Hash;
 use std::ops::Add;
 
 use super::frontier::Frontier;
-use super::graph::{GraphBuilder, GraphSolver, PathSearchTree};
+use super::graph::{GraphBuilder, GraphSolver, PathConstraints, PathSearchTree};
 use super::numtraits::{IntoOrd, Zero};
+use super::yen::{self, ShortestPath};
 
 /// Dijkstra path search.
 pub struct Dijkstra<T, W> {
@@ -31,69 +32,105 @@ where
         debug_assert!(links.iter().all(|(_, w)| *w >= W::ZERO), "Negative weight detected");
         self.nodes.insert(node_tag, links);
     }
+
+    fn remove_node(&mut self, node_tag: &T) {
+        self.nodes.remove(node_tag);
+    }
+
+    fn links(&self, node_tag: &T) -> Option<&[(T, W)]> {
+        self.nodes.get(node_tag).map(|links| links.as_slice())
+    }
 }
 
-impl<T, W> GraphSolver<T, W> for Dijkstra<T, W>
+impl<T, W> ShortestPath<T, W> for Dijkstra<T, W>
 where
     T: Clone + Eq + Ord + Hash,
     W: Clone + PartialEq + PartialOrd + IntoOrd + Add<Output = W> + Zero,
 {
-    fn path(&self, from: &T, to: &T) -> Vec<T> {
-        let mut path = self.reverse_path(from, to);
-
-        // Reverse the path, so the result will be from `from` to `to`
-        path.reverse();
-
-        path
-    }
-
-    fn reverse_path(&self, from: &T, to: &T) -> Vec<T> {
+    fn search(
+        &self,
+        from: &T,
+        to: &T,
+        constraints: &PathConstraints<T>,
+        excluded_nodes: &HashSet<T>,
+        excluded_links: &HashSet<(T, T)>,
+        max_hops: Option<usize>,
+    ) -> Option<(Vec<T>, W)> {
         // Don't run when we don't have nodes set
         if self.nodes.is_empty() {
-            return Vec::new();
+            return None;
         }
 
-        // Algorithm state
-        let mut explored = HashSet::<T>::new();
-        let mut frontier = Frontier::<T, W>::new();
-        let mut previous = HashMap::<T, T>::new();
+        // Algorithm state. When there is a hop limit, each node is tracked together with the number of hops to it,
+        // because a more expensive path with fewer hops could be the only one fitting the limit.
+        // Without limit the number of hops is always 0, which makes it the plain Dijkstra.
+        let mut explored = HashMap::<T, usize>::new();
+        let mut frontier = Frontier::<(T, usize), W>::new();
+        let mut previous = HashMap::<(T, usize), (T, usize)>::new();
 
         // The resulting reversed path
         let mut rev_path = Vec::<T>::new();
+        let mut path_cost = W::ZERO;
 
         // Add the starting point to the frontier, it will be the first node visited
-        frontier.push(from.clone(), W::ZERO);
+        frontier.push((from.clone(), 0), W::ZERO);
 
         // Run until we have visited every node in the frontier
-        while let Some((tag, cost)) = frontier.pop() {
+        while let Some(((tag, hops), cost)) = frontier.pop() {
             // When the node with the lowest cost in the frontier is our goal node, we're done.
             if tag == *to {
-                let mut cur_tag = tag;
-                while let Some(prev_tag) = previous.get(&cur_tag) {
-                    rev_path.push(cur_tag.clone());
-                    cur_tag = prev_tag.clone();
+                let mut cur_state = (tag, hops);
+                while let Some(prev_state) = previous.get(&cur_state) {
+                    rev_path.push(cur_state.0.clone());
+                    cur_state = prev_state.clone();
                 }
+                path_cost = cost;
                 break;
             }
 
+            // Node already explored via cheaper path with no more hops can't give anything better
+            if explored.get(&tag).map(|&h| h <= hops).unwrap_or(false) {
+                continue;
+            }
+
             // Add the current node to the explored set
-            explored.insert(tag.clone());
+            explored.insert(tag.clone(), hops);
+
+            let n_hops = match max_hops {
+                Some(max_hops) if hops >= max_hops => continue,
+                Some(_) => hops + 1,
+                None => 0,
+            };
 
             // Loop all the neighboring nodes
             if let Some(neighbors) = self.nodes.get(&tag) {
                 for (n_tag, n_cost) in neighbors.iter() {
                     // If we already explored the node - skip it
-                    if explored.contains(n_tag) {
+                    if explored.get(n_tag).map(|&h| h <= n_hops).unwrap_or(false) {
                         continue;
                     }
 
+                    // Skip excluded nodes and links
+                    if constraints.excluded_nodes.contains(n_tag) || excluded_nodes.contains(n_tag) {
+                        continue;
+                    }
+                    if !excluded_links.is_empty() && excluded_links.contains(&(tag.clone(), n_tag.clone())) {
+                        continue;
+                    }
+                    if let Some(link_filter) = constraints.link_filter {
+                        if !link_filter(&tag, n_tag) {
+                            continue;
+                        }
+                    }
+
                     let node_cost = cost.clone() + n_cost.clone();
 
                     // If the neighboring node is not yet in the frontier, we add it with the correct cost.
                     // Otherwise we only update the cost of this node in the frontier when it's below what's currently set.
-                    let updated = frontier.try_insert_or_decrease_cost(n_tag, node_cost);
+                    let n_state = (n_tag.clone(), n_hops);
+                    let updated = frontier.try_insert_or_decrease_cost(&n_state, node_cost);
                     if updated {
-                        previous.insert(n_tag.clone(), tag.clone());
+                        previous.insert(n_state, (tag.clone(), hops));
                     }
                 }
             }
@@ -101,13 +138,49 @@ where
 
         // Check if path not found
         if rev_path.is_empty() {
-            return rev_path;
+            return None;
         }
 
         // Add the origin waypoint at the end of the array
         rev_path.push(from.clone());
 
-        rev_path
+        Some((rev_path, path_cost))
+    }
+
+    fn path_cost(&self, path: &[T]) -> Option<W> {
+        let mut total = W::ZERO;
+        for pair in path.windows(2) {
+            let links = self.nodes.get(&pair[0])?;
+            let (_, cost) = links.iter().find(|(tag, _)| *tag == pair[1])?;
+            total = total + cost.clone();
+        }
+        Some(total)
+    }
+}
+
+impl<T, W> GraphSolver<T, W> for Dijkstra<T, W>
+where
+    T: Clone + Eq + Ord + Hash,
+    W: Clone + PartialEq + PartialOrd + IntoOrd + Add<Output = W> + Zero,
+{
+    fn path(&self, from: &T, to: &T) -> Vec<T> {
+        let mut path = self.reverse_path(from, to);
+
+        // Reverse the path, so the result will be from `from` to `to`
+        path.reverse();
+
+        path
+    }
+
+    fn reverse_path(&self, from: &T, to: &T) -> Vec<T> {
+        self.search(from, to, &PathConstraints::default(), &HashSet::new(), &HashSet::new(), None)
+            .map(|(rev_path, _)| rev_path)
+            .unwrap_or_default()
+    }
+
+    /// Yen's algorithm.
+    fn k_shortest_paths(&self, from: &T, to: &T, k: usize, constraints: &PathConstraints<T>) -> Vec<Vec<T>> {
+        yen::k_shortest_paths(self, from, to, k, constraints)
     }
 
     fn path_search_tree(&self, start: &T) -> PathSearchTree<T> {
@@ -1,20 +1,23 @@
 //! Frontier for the Dijkstra algorithm.
 
 use std::cmp::Reverse;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap};
 use std::hash::Hash;
 
 use super::numtraits::IntoOrd;
 
 /// Frontier for the Dijkstra algorithm.
-/// Internally uses binary search to maintain priority queue and hashmap-based index.
-pub(super) struct Frontier<T, W> {
-    /// Stores items in cost-descending order,
-    /// so the item with the lowest cost is placed at the end of the array.
-    queue: Vec<(T, W)>,
-
-    /// Links node tag `<T>` to the index in the `queue`.
-    index: HashMap<T, usize>,
+/// Internally uses binary heap as priority queue and hashmap with current costs of the queued nodes.
+/// When the cost of a node is decreased, the new item is pushed to the heap and the old one is left there,
+/// to be skipped later when popped, so every operation is `O(log n)`.
+pub(super) struct Frontier<T, W: IntoOrd> {
+    /// Items ordered by cost in descending order, then by tag,
+    /// so the item with the lowest cost is on top of the heap.
+    /// May contain outdated items, which don't match the current cost in `costs`.
+    heap: BinaryHeap<(Reverse<W::Output>, T)>,
+
+    /// Current cost of each node tag `<T>` in the queue.
+    costs: HashMap<T, W>,
 }
 
 impl<T, W> Frontier<T, W>
@@ -25,47 +28,47 @@ where
     /// Create new empty instance.
     pub fn new() -> Frontier<T, W> {
         Frontier {
-            queue: Vec::new(),
-            index: HashMap::new(),
+            heap: BinaryHeap::new(),
+            costs: HashMap::new(),
         }
     }
 
-    /// Sorts by cost in descending order, then by tag.
-    fn key_fn(item: &(T, W)) -> impl Ord {
-        let (t, w) = item;
-        (Reverse(w.clone().into_ord()), t.clone())
+    /// Heap key of an item.
+    fn key(tag: &T, weight: &W) -> (Reverse<W::Output>, T) {
+        (Reverse(weight.clone().into_ord()), tag.clone())
     }
 
-    /// Find position in the queue where to insert a new item.
-    fn insertion_pos(&self, item: &(T, W)) -> usize {
-        match self.queue.binary_search_by_key(&Self::key_fn(item), Self::key_fn) {
-            Ok(index) | Err(index) => index,
+    /// Drop outdated items from the top of the heap, so the top item (if any) is an actual one.
+    fn skip_outdated(&mut self) {
+        while let Some((Reverse(cost), tag)) = self.heap.peek() {
+            match self.costs.get(tag) {
+                Some(weight) if weight.clone().into_ord() == *cost => break,
+                _ => {
+                    self.heap.pop();
+                }
+            }
         }
     }
 
     /// Insert a node with associated cost into the priority queue.
     pub fn push(&mut self, tag: T, weight: W) {
-        let item = (tag.clone(), weight);
-        let index = self.insertion_pos(&item);
-        self.queue.insert(index, item);
-        self.index.iter_mut().for_each(|(_, old_idx)| {
-            if *old_idx >= index {
-                *old_idx += 1;
-            }
-        });
-        self.index.insert(tag, index);
+        self.heap.push(Self::key(&tag, &weight));
+        self.costs.insert(tag, weight);
     }
 
     /// Extract node with the least cost from the queue.
     pub fn pop(&mut self) -> Option<(T, W)> {
-        if let Some((tag, weight)) = self.queue.pop() {
-            // Since pop() removed the item with the highest index,
-            // no need to correct any other items in index.
-            self.index.remove(&tag);
-            Some((tag, weight))
-        } else {
-            None
-        }
+        self.skip_outdated();
+        let (_, tag) = self.heap.pop()?;
+        let weight = self.costs.remove(&tag).expect("frontier item without cost");
+        Some((tag, weight))
+    }
+
+    /// The least cost of the nodes in the queue, if it is not empty.
+    pub fn peek_cost(&mut self) -> Option<&W> {
+        self.skip_outdated();
+        let (_, tag) = self.heap.peek()?;
+        self.costs.get(tag)
     }
 
     /// Insert a node if it is not in the queue yet,
@@ -73,28 +76,9 @@ where
     /// Returns `true` if the node was either inserted or updated,
     /// otherwise (node existed and current cost is less that the new one) `false`.
     pub fn try_insert_or_decrease_cost(&mut self, tag: &T, new_cost: W) -> bool {
-        if let Some(&i) = self.index.get(tag) {
-            if new_cost < self.queue[i].1 {
-                // Remove old item
-                self.queue.remove(i);
-                self.index.remove(tag);
-                self.index.iter_mut().for_each(|(_, old_idx)| {
-                    if *old_idx >= i {
-                        *old_idx -= 1;
-                    }
-                });
-
-                // Re-insert with updated cost
-                let new_item = (tag.clone(), new_cost);
-                let new_index = self.insertion_pos(&new_item);
-                self.queue.insert(new_index, new_item);
-                self.index.iter_mut().for_each(|(_, old_idx)| {
-                    if *old_idx >= new_index {
-                        *old_idx += 1;
-                    }
-                });
-                self.index.insert(tag.clone(), new_index);
-
+        if let Some(cost) = self.costs.get(tag) {
+            if new_cost < *cost {
+                self.push(tag.clone(), new_cost);
                 true
             } else {
                 false
@@ -169,7 +153,17 @@ fn test_decrease_cost() {
     f.push("X", 2.0);
     f.push("Y", 3.0);
     assert_eq!(f.try_insert_or_decrease_cost(&"Y", 1.0), true);
+    assert_eq!(f.peek_cost(), Some(&1.0));
     assert_eq!(f.pop(), Some(("Y", 1.0)));
+    assert_eq!(f.peek_cost(), Some(&2.0));
     assert_eq!(f.pop(), Some(("X", 2.0)));
+    assert_eq!(f.peek_cost(), None);
+    assert_eq!(f.pop(), None);
+
+    // Ties are broken by tag, the greatest first
+    f.push("A", 1.0);
+    f.push("B", 1.0);
+    assert_eq!(f.pop(), Some(("B", 1.0)));
+    assert_eq!(f.pop(), Some(("A", 1.0)));
     assert_eq!(f.pop(), None);
 }
@@ -1,8 +1,17 @@
 //! Path solver graph traits.
 
+use std::collections::HashSet;
+
 /// Graph building functions.
 pub trait GraphBuilder<T, W> {
+    /// Add node with its outgoing links, replacing the links if the node already exists.
     fn add_node<I: IntoIterator<Item = (T, W)>>(&mut self, node_tag: T, links: I);
+
+    /// Remove node together with its outgoing links. Links from other nodes are left intact.
+    fn remove_node(&mut self, node_tag: &T);
+
+    /// Outgoing links of a node, if it exists.
+    fn links(&self, node_tag: &T) -> Option<&[(T, W)]>;
 }
 
 /// Path finding functions.
@@ -13,6 +22,9 @@ pub trait GraphSolver<T, W> {
     /// Find a reverse path from `to` node to `from` node.
     fn reverse_path(&self, from: &T, to: &T) -> Vec<T>;
 
+    /// Find up to `k` shortest loopless paths from `from` node to `to` node satisfying the constraints, cheapest first.
+    fn k_shortest_paths(&self, from: &T, to: &T, k: usize, constraints: &PathConstraints<T>) -> Vec<Vec<T>>;
+
     /// Build a Path Search Tree from a given node.
     fn path_search_tree(&self, start: &T) -> PathSearchTree<T>;
 }
@@ -25,3 +37,28 @@ pub struct PathSearchTree<T> {
     /// Each tuple contains ending node and intermediate nodes as a vector.
     pub paths: Vec<(T, Vec<T>)>,
 }
+
+/// Restrictions on the paths being searched.
+pub struct PathConstraints<'a, T> {
+    /// Nodes the path must not go through.
+    pub excluded_nodes: HashSet<T>,
+
+    /// Maximum number of links in the path.
+    pub max_hops: Option<usize>,
+
+    /// Links which can be used in the path (all links if not set).
+    pub link_filter: Option<LinkFilter<'a, T>>,
+}
+
+/// Predicate telling whether the link from the first node to the second one can be used.
+pub type LinkFilter<'a, T> = &'a dyn Fn(&T, &T) -> bool;
+
+impl<T> Default for PathConstraints<'_, T> {
+    fn default() -> Self {
+        PathConstraints {
+            excluded_nodes: HashSet::new(),
+            max_hops: None,
+            link_filter: None,
+        }
+    }
+}
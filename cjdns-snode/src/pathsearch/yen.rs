@@ -0,0 +1,95 @@
+//! Yen's k shortest paths algorithm.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::ops::Add;
+
+use super::graph::PathConstraints;
+use super::numtraits::IntoOrd;
+
+/// Single shortest path search used by the Yen's algorithm.
+pub(super) trait ShortestPath<T, W> {
+    /// Find a reverse path from `to` node to `from` node together with its cost,
+    /// satisfying the constraints and not going through any of the extra excluded nodes or links.
+    /// If `max_hops` is set, paths longer than that are not considered.
+    fn search(
+        &self,
+        from: &T,
+        to: &T,
+        constraints: &PathConstraints<T>,
+        excluded_nodes: &HashSet<T>,
+        excluded_links: &HashSet<(T, T)>,
+        max_hops: Option<usize>,
+    ) -> Option<(Vec<T>, W)>;
+
+    /// Total cost of the path, or `None` if some of its links don't exist.
+    fn path_cost(&self, path: &[T]) -> Option<W>;
+}
+
+/// Find up to `k` shortest loopless paths from `from` node to `to` node satisfying the constraints, cheapest first.
+pub(super) fn k_shortest_paths<T, W, S>(solver: &S, from: &T, to: &T, k: usize, constraints: &PathConstraints<T>) -> Vec<Vec<T>>
+where
+    T: Clone + Eq + Hash,
+    W: Clone + IntoOrd + Add<Output = W>,
+    S: ShortestPath<T, W>,
+{
+    let mut found = Vec::<(Vec<T>, W)>::new();
+    let mut candidates = Vec::<(Vec<T>, W)>::new();
+
+    if k == 0 {
+        return Vec::new();
+    }
+
+    if let Some((mut path, cost)) = solver.search(from, to, constraints, &HashSet::new(), &HashSet::new(), constraints.max_hops) {
+        path.reverse();
+        found.push((path, cost));
+    } else {
+        return Vec::new();
+    }
+
+    while found.len() < k {
+        let (last_path, _) = found.last().expect("no paths found");
+
+        // Each node of the last found path except the destination is tried as a spur node
+        for i in 0..last_path.len() - 1 {
+            let spur_node = &last_path[i];
+            let root_path = &last_path[..=i];
+
+            // Links from the spur node used by already found paths with the same root are excluded,
+            // as well as root path nodes, so the new path is different and loopless.
+            let excluded_links = found
+                .iter()
+                .filter(|(path, _)| path.len() > i + 1 && path[..=i] == *root_path)
+                .map(|(path, _)| (path[i].clone(), path[i + 1].clone()))
+                .collect::<HashSet<_>>();
+            let excluded_nodes = root_path[..i].iter().cloned().collect::<HashSet<_>>();
+
+            let spur_max_hops = constraints.max_hops.map(|max_hops| max_hops.saturating_sub(i));
+            let spur = solver.search(spur_node, to, constraints, &excluded_nodes, &excluded_links, spur_max_hops);
+            if let Some((spur_rev_path, spur_cost)) = spur {
+                let root_cost = match solver.path_cost(root_path) {
+                    Some(cost) => cost,
+                    None => continue, // Shouldn't happen since the root path was found in this graph
+                };
+                let mut path = root_path[..i].to_vec();
+                path.extend(spur_rev_path.into_iter().rev());
+                if !found.iter().chain(candidates.iter()).any(|(p, _)| *p == path) {
+                    candidates.push((path, root_cost + spur_cost));
+                }
+            }
+        }
+
+        // Cheapest candidate is the next shortest path
+        let best = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (path, cost))| (cost.clone().into_ord(), path.len()))
+            .map(|(index, _)| index);
+        match best {
+            Some(index) => found.push(candidates.swap_remove(index)),
+            None => break,
+        }
+    }
+
+    found.into_iter().map(|(path, _)| path).collect()
+}
@@ -1,23 +1,31 @@
 //! Connecting to other supernodes
 
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
+use futures::future::AbortHandle;
 use futures::{Future, SinkExt, StreamExt};
+use http::header::{HeaderValue, AUTHORIZATION};
 use http::Uri;
 use parking_lot::Mutex;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::time;
 use tokio_tungstenite as websocket;
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
 use cjdns_ann::AnnHash;
 
 use crate::message::{Message, MessageData};
 use crate::msg;
 use crate::server::websock::WebSock;
+use crate::utils::clock::Clock;
 use crate::utils::rand::seed;
 use crate::utils::seq::Seq;
+use crate::utils::shutdown::Shutdown;
 
 pub(crate) use self::ann_list::AnnData;
 use self::ann_list::AnnList;
@@ -28,6 +36,7 @@ use self::peer_list::PeerList;
 
 mod ann_list;
 mod info;
+mod outgoing;
 mod peer;
 mod peer_list;
 mod ping;
@@ -37,37 +46,70 @@ pub struct Peers {
     anns: Mutex<AnnList>,
     msg_id_seq: Seq,
     announce_tx: mpsc::Sender<AnnData>,
+    /// Running outgoing connection tasks by peer address, so they can be cancelled
+    outgoing_conns: Mutex<HashMap<String, AbortHandle>>,
+    /// Secret presented to the peer supernodes as a bearer token when connecting
+    peer_secret: Mutex<Option<String>>,
+    clock: Arc<dyn Clock>,
+    /// Closes all the connections and stops reconnecting when triggered
+    shutdown: Shutdown,
 }
 
 impl Peers {
     const VERSION: u64 = 1;
 }
 
-pub fn create_peers() -> (Peers, mpsc::Receiver<AnnData>) {
+pub fn create_peers(clock: Arc<dyn Clock>) -> (Peers, mpsc::Receiver<AnnData>) {
     const QUEUE_SIZE: usize = 256;
     let (tx, rx) = mpsc::channel(QUEUE_SIZE);
-    let peers = Peers::new(tx);
+    let peers = Peers::new(tx, clock);
     (peers, rx)
 }
 
 impl Peers {
     /// Create new instance of Peers + announce sender
-    fn new(ann_tx: mpsc::Sender<AnnData>) -> Self {
+    fn new(ann_tx: mpsc::Sender<AnnData>, clock: Arc<dyn Clock>) -> Self {
         Peers {
             peers: PeerList::new(),
             anns: Mutex::new(AnnList::new()),
             msg_id_seq: Seq::new(seed()),
             announce_tx: ann_tx,
+            outgoing_conns: Mutex::new(HashMap::new()),
+            peer_secret: Mutex::new(None),
+            clock,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Set the secret to present to the peer supernodes, used starting from the next connection attempt.
+    pub fn set_peer_secret(&self, secret: Option<String>) {
+        *self.peer_secret.lock() = secret;
+    }
+
+    /// Close all the peer connections with a WebSocket close frame and stop reconnecting.
+    /// Waits up to `timeout` for the connections to close.
+    pub async fn close_all(&self, timeout: Duration) {
+        self.shutdown.trigger();
+        let closed = async {
+            while !self.peers.list(|peer| peer.id).is_empty() {
+                time::delay_for(Duration::from_millis(50)).await;
+            }
+        };
+        if time::timeout(timeout, closed).await.is_err() {
+            warn!("Some peer connections didn't close in time");
         }
     }
 
     /// Asynchronously start connecting to the specified peer supernode.
     /// If the connection can't be established or closed by the remote side,
-    /// it will be reconnected automatically after a delay.
+    /// it will be reconnected automatically after a delay, unless the peers are being closed.
     pub async fn connect_to(&self, uri: Uri) {
         debug!("Connecting to {}", uri);
-        loop {
-            let res = websocket::connect_async(&uri).await;
+        while !self.shutdown.is_triggered() {
+            let res = match self.connect_request(&uri) {
+                Ok(request) => websocket::connect_async(request).await,
+                Err(err) => Err(err),
+            };
 
             let sucessfully_connected = res.is_ok();
 
@@ -101,10 +143,22 @@ impl Peers {
             } else {
                 Duration::from_secs(10)
             };
-            time::delay_for(delay).await;
+            select! {
+                _ = time::delay_for(delay) => {}
+                _ = self.shutdown.wait() => {}
+            }
         }
     }
 
+    /// WebSocket handshake request, with the peer secret if there is one.
+    fn connect_request(&self, uri: &Uri) -> Result<http::Request<()>, tungstenite::Error> {
+        let mut request = uri.into_client_request()?;
+        if let Some(secret) = self.peer_secret.lock().as_ref() {
+            request.headers_mut().insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", secret))?);
+        }
+        Ok(request)
+    }
+
     pub async fn accept_incoming_connection(&self, from_ipv6: String, ws_stream: impl WebSock) -> Result<(), Error> {
         info!("Incoming connection from {}", from_ipv6);
         self.incoming(from_ipv6, ws_stream).await
@@ -135,6 +189,7 @@ impl Peers {
     async fn incoming(&self, addr: String, ws_stream: impl WebSock) -> Result<(), Error> {
         // Create peer & websocket service task
         let (mut peer, ws_task) = self.create_peer(addr, ws_stream, PeerType::Incoming);
+        let peer_guard = PeerGuard::new(self, &peer);
 
         // Send handshake
         peer.send_msg(msg![0, "HELLO", Self::VERSION]).await?;
@@ -152,7 +207,7 @@ impl Peers {
         let res = ws_task.await;
 
         // Drop peer
-        self.drop_peer(peer);
+        drop(peer_guard);
 
         res
     }
@@ -162,6 +217,7 @@ impl Peers {
     async fn outgoing(&self, addr: String, ws_stream: impl WebSock) -> Result<(), Error> {
         // Create peer & websocket service task
         let (mut peer, ws_task) = self.create_peer(addr, ws_stream, PeerType::Outgoing);
+        let peer_guard = PeerGuard::new(self, &peer);
 
         // Send handshake
         peer.send_msg(msg![0, "OLLEH", Self::VERSION]).await?;
@@ -170,7 +226,7 @@ impl Peers {
         let res = ws_task.await;
 
         // Drop peer
-        self.drop_peer(peer);
+        drop(peer_guard);
 
         res
     }
@@ -184,7 +240,7 @@ impl Peers {
         let ann_tx = self.announce_tx.clone();
 
         // Create peer struct
-        let peer = self.peers.create_peer(peer_type, addr, msg_tx);
+        let peer = self.peers.create_peer(peer_type, addr, msg_tx, self.clock.instant());
 
         // Create the websocket servicing task
         let ws_task = self.run_websocket(peer.clone(), ws_stream, msg_rx, ann_tx);
@@ -219,6 +275,11 @@ impl Peers {
                     let message = Message::decode_msgpack(&bytes)?;
                     self.handle_message(peer.clone(), message, &mut ann_tx).await?;
                 }
+                _ = self.shutdown.wait() => {
+                    // Closing the sink sends the close frame
+                    ws_write.close().await?;
+                    break;
+                }
                 else => break,
             }
         }
@@ -229,7 +290,7 @@ impl Peers {
     async fn handle_message(&self, mut peer: Peer, message: Message, ann_tx: &mut mpsc::Sender<AnnData>) -> Result<(), Error> {
         let Message(id, msg) = message;
 
-        *peer.last_msg_time.write() = Instant::now();
+        *peer.last_msg_time.write() = self.clock.instant();
 
         use MessageData::*;
 
@@ -298,3 +359,22 @@ impl Peers {
         Ok(())
     }
 }
+
+/// Removes the peer from the peer list when dropped.
+/// This way the peer is removed even if its connection task is cancelled.
+struct PeerGuard<'a> {
+    peers: &'a Peers,
+    peer_id: u64,
+}
+
+impl<'a> PeerGuard<'a> {
+    fn new(peers: &'a Peers, peer: &Peer) -> Self {
+        PeerGuard { peers, peer_id: peer.id }
+    }
+}
+
+impl Drop for PeerGuard<'_> {
+    fn drop(&mut self) {
+        self.peers.peers.remove_peer(self.peer_id);
+    }
+}
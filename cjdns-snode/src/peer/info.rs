@@ -1,5 +1,7 @@
 //! Info about connections to peer supernodes
 
+use std::time::{Duration, Instant};
+
 use crate::peer::{Peer, PeerList, Peers};
 
 pub struct PeersInfo {
@@ -13,13 +15,14 @@ pub struct PeerInfo {
     pub outstanding_requests: usize,
     pub msgs_on_wire: usize,
     pub msg_queue: usize,
+    pub last_msg_age: Duration,
 }
 
 impl Peers {
     pub fn get_info(&self) -> PeersInfo {
         let (hash_count, ann_count) = self.anns.lock().info();
         PeersInfo {
-            peers: self.peers.info(),
+            peers: self.peers.info(self.clock.instant()),
             announcements: hash_count,
             ann_by_hash_len: ann_count,
         }
@@ -27,18 +30,19 @@ impl Peers {
 }
 
 impl PeerList {
-    fn info(&self) -> Vec<PeerInfo> {
-        self.list(|peer| peer.info())
+    fn info(&self, now: Instant) -> Vec<PeerInfo> {
+        self.list(|peer| peer.info(now))
     }
 }
 
 impl Peer {
-    fn info(&self) -> PeerInfo {
+    fn info(&self, now: Instant) -> PeerInfo {
         PeerInfo {
             addr: self.addr.clone(),
             outstanding_requests: self.get_outstanding_reqs_count(),
             msgs_on_wire: 0, //TODO No such concept in rust code - ask CJ what to do with it, remove or keep 0 for compatibility?
             msg_queue: 0,    //TODO originally "self.msg_queue.len()", not easy to get in Rust code - is it really needed, or can be dropped?
+            last_msg_age: now.saturating_duration_since(*self.last_msg_time.read()),
         }
     }
 }
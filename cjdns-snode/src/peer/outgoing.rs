@@ -0,0 +1,44 @@
+//! Managing the set of outgoing connections to peer supernodes
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::future::abortable;
+use http::Uri;
+use tokio::task;
+
+use crate::peer::Peers;
+
+impl Peers {
+    /// Make the set of peer supernodes we are connecting to match the given list of addresses.
+    /// Connections to new addresses are started, connections to addresses no longer in the list are cancelled.
+    pub fn set_outgoing_peers(self: &Arc<Self>, peer_addrs: &[String]) {
+        let mut conns = self.outgoing_conns.lock();
+
+        conns.retain(|addr, abort_handle| {
+            let keep = peer_addrs.contains(addr);
+            if !keep {
+                info!("Disconnecting from {}", addr);
+                abort_handle.abort();
+            }
+            keep
+        });
+
+        for peer_addr in peer_addrs {
+            if conns.contains_key(peer_addr) {
+                continue;
+            }
+            match Uri::from_str(peer_addr) {
+                Ok(uri) => {
+                    let peers = Arc::clone(self);
+                    let (conn_task, abort_handle) = abortable(async move { peers.connect_to(uri).await });
+                    task::spawn(conn_task);
+                    conns.insert(peer_addr.clone(), abort_handle);
+                }
+                Err(err) => {
+                    error!("Unable to connect to {}: {}", peer_addr, err);
+                }
+            }
+        }
+    }
+}
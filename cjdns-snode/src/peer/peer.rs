@@ -34,12 +34,12 @@ pub(super) enum PeerType {
 pub(super) struct PeerConnectionClosed;
 
 impl Peer {
-    pub(super) fn new(id: u64, addr: String, peer_type: PeerType, msg_queue: mpsc::Sender<Message>) -> Self {
+    pub(super) fn new(id: u64, addr: String, peer_type: PeerType, msg_queue: mpsc::Sender<Message>, now: Instant) -> Self {
         Peer {
             id,
             addr,
             peer_type,
-            last_msg_time: Arc::new(RwLock::new(Instant::now())),
+            last_msg_time: Arc::new(RwLock::new(now)),
             outstanding_reqs: Arc::new(Mutex::new(HashSet::new())),
             msg_queue,
         }
@@ -26,9 +26,9 @@ impl PeerList {
         self.peers.read().iter().map(f).collect()
     }
 
-    pub(super) fn create_peer(&self, peer_type: PeerType, addr: String, msg_queue: mpsc::Sender<Message>) -> Peer {
+    pub(super) fn create_peer(&self, peer_type: PeerType, addr: String, msg_queue: mpsc::Sender<Message>, now: Instant) -> Peer {
         let peer_id = self.peer_id_seq.next();
-        let peer = Peer::new(peer_id, addr, peer_type, msg_queue);
+        let peer = Peer::new(peer_id, addr, peer_type, msg_queue, now);
         self.peers.write().push(peer.clone());
         peer
     }
@@ -37,13 +37,12 @@ impl PeerList {
         self.peers.write().retain(|p| p.id != id);
     }
 
-    pub(super) fn get_timed_out_peers(&self, drop_after: Duration, ping_after: Duration) -> (Vec<Peer>, Vec<Peer>) {
+    pub(super) fn get_timed_out_peers(&self, now: Instant, drop_after: Duration, ping_after: Duration) -> (Vec<Peer>, Vec<Peer>) {
         let (mut ping_list, mut drop_list) = (Vec::new(), Vec::new());
 
         // Check last message time for every peer
-        let now = Instant::now();
         for peer in self.peers.read().iter().cloned() {
-            let lag = now - *peer.last_msg_time.read();
+            let lag = now.saturating_duration_since(*peer.last_msg_time.read());
             if lag > drop_after {
                 drop_list.push(peer);
             } else if lag > ping_after {
@@ -17,7 +17,8 @@ impl Peers {
     }
 
     async fn do_pings(&self) {
-        let (ping_list, drop_list) = self.peers.get_timed_out_peers(Self::DROP_AFTER, Self::PING_AFTER);
+        let now = self.clock.instant();
+        let (ping_list, drop_list) = self.peers.get_timed_out_peers(now, Self::DROP_AFTER, Self::PING_AFTER);
 
         // Ping stale peers
         for mut peer in ping_list {
@@ -1,54 +1,121 @@
 //! CJDNS supernode implementation.
 
 use std::collections::HashMap;
-use std::str::FromStr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 
 use anyhow::Error;
 use anyhow::Result;
 use futures::future::try_join_all;
 use futures::StreamExt;
-use http::Uri;
-use parking_lot::Mutex;
-use tokio::task;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::Notify;
+use tokio::{select, signal, task, time};
 
 use cjdns_ann::{AnnHash, Announcement, AnnouncementPacket, Entity, LINK_STATE_SLOTS};
 use cjdns_keys::CJDNS_IP6;
 
 use crate::config::Config;
 use crate::peer::{create_peers, AnnData, Peers};
+use crate::server::asymmetry::AsymmetricLinks;
+use crate::server::debug_trace::DebugTrace;
+use crate::server::events::{Events, TopologyEvent};
 use crate::server::link::{mk_link, Link, LinkStateEntry};
 use crate::server::nodes::{Node, Nodes};
+use crate::server::rate_limit::RateLimiter;
 use crate::server::route::Routing;
-use crate::utils::task::periodic_task;
-use crate::utils::timestamp::{mktime, time_diff};
-
+use crate::server::stats::Stats;
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::shutdown::Shutdown;
+use crate::utils::task::{periodic_async_task, periodic_task};
+use crate::utils::timestamp::{make_timestamp, mktime, time_diff};
+
+mod analysis;
+mod asymmetry;
+mod auth;
+mod bootstrap;
+mod cost;
+mod debug_trace;
+mod events;
 mod hash;
 mod link;
+mod metrics;
 mod nodes;
+mod persist;
+mod policy;
+mod rate_limit;
+mod reconfig;
+mod replay;
 mod route;
 mod service;
+mod stats;
+mod topology;
 mod utils;
 mod webserver;
 pub mod websock;
 
+pub use self::replay::{replay, ReplayOptions};
+
 const KEEP_TABLE_CLEAN_CYCLE: Duration = Duration::from_secs(30);
 
+/// How long to wait for the local router and the peers to let go when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Server entry point. Requires config (loaded from an external file) to run.
-pub async fn main(config: Config) -> Result<()> {
+/// The config file path is needed to reload the config at runtime.
+/// Optionally, the node table can be bootstrapped from another supernode's `/dump` URL or a dump file.
+pub async fn main(config_file: PathBuf, config: Config, bootstrap_from: Option<String>) -> Result<()> {
     // Background tasks we are going to spawn
     let mut tasks = Vec::new();
 
     // The server context instance
-    let (peers, announces) = create_peers();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let (peers, announces) = create_peers(Arc::clone(&clock));
     let peers = Arc::new(peers);
-    let server = Arc::new(Server::new(Arc::clone(&peers)));
+    let server = Arc::new(Server::new(Arc::clone(&peers), clock, config_file, config.clone()));
+
+    // Restore the node table saved by the previous run, if configured
+    if let Some(state_file) = config.state_file.as_ref() {
+        if let Err(err) = persist::load_state(&server, state_file).await {
+            error!("Failed to restore state: {}", err);
+        }
+    }
+
+    // Get full view of the network from another supernode, if requested
+    if let Some(source) = bootstrap_from.as_ref() {
+        if let Err(err) = bootstrap::bootstrap_from(&server, source).await {
+            error!("Failed to bootstrap: {}", err);
+        }
+    }
+
+    // Run state saving task
+    if let Some(state_file) = config.state_file.clone() {
+        let server = Arc::clone(&server);
+        let period = Duration::from_secs(config.state_save_interval);
+        let h = task::spawn(periodic_async_task(period, move || {
+            let server = Arc::clone(&server);
+            let state_file = state_file.clone();
+            async move {
+                if let Err(err) = persist::save_state(&server, &state_file).await {
+                    error!("Failed to save state: {}", err);
+                }
+            }
+        }));
+        tasks.push(h);
+    }
 
     // Run timeout task
     {
         let server = Arc::clone(&server);
-        let h = task::spawn(periodic_task(KEEP_TABLE_CLEAN_CYCLE, move || server.nodes.keep_table_clean()));
+        let h = task::spawn(periodic_task(KEEP_TABLE_CLEAN_CYCLE, move || {
+            server.forget_old_nodes();
+            if let Some(rate_limiter) = server.rate_limiter.as_ref() {
+                rate_limiter.cleanup(server.clock.instant());
+            }
+            // Keep track of how long the links are asymmetric even if nobody asks
+            server.asymmetric_links.update(&server.nodes, server.clock.instant());
+        }));
         tasks.push(h);
     }
 
@@ -75,40 +142,87 @@ pub async fn main(config: Config) -> Result<()> {
         tasks.push(h);
     }
 
-    // Start supernode HTTP/WebSocket server task
+    // Start supernode HTTP/WebSocket server tasks
+    for listener in config.listeners.iter() {
+        let server = Arc::clone(&server);
+        let h = task::spawn(webserver::listener_task(server, listener.clone()));
+        tasks.push(h);
+    }
+
+    // Reload config on SIGHUP
+    #[cfg(unix)]
     {
         let server = Arc::clone(&server);
-        let h = task::spawn(webserver::test_srv_task(server));
+        let h = task::spawn(reconfig::reload_on_sighup(server));
         tasks.push(h);
     }
 
     // Connect to peer supernodes
-    for peer_addr in config.peers.iter() {
-        match Uri::from_str(peer_addr) {
-            Ok(uri) => {
-                let peers = Arc::clone(&peers);
-                let h = task::spawn(async move { peers.connect_to(uri).await });
-                tasks.push(h);
-            }
-            Err(err) => {
-                error!("Unable to connect to {}: {}", peer_addr, err);
+    peers.set_peer_secret(config.auth.as_ref().and_then(|auth| auth.peer_secret.clone()));
+    peers.set_outgoing_peers(&config.peers);
+
+    // Await all spawned tasks, or until asked to terminate
+    let res: Result<()> = select! {
+        res = try_join_all(tasks) => res.map(|_| ()).map_err(|e| e.into()),
+        _ = termination_signal() => {
+            info!("Terminating, shutting down");
+            server.shut_down(config.connect).await;
+            Ok(())
+        }
+    };
+
+    // Save the node table so the next run could pick it up
+    if let Some(state_file) = config.state_file.as_ref() {
+        if let Err(err) = persist::save_state(&server, state_file).await {
+            error!("Failed to save state: {}", err);
+        }
+    }
+
+    res
+}
+
+/// Completes when the process is asked to terminate: on Ctrl+C (SIGINT), or SIGTERM on Unix.
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+
+        match unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                select! {
+                    _ = signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
             }
+            Err(err) => error!("Unable to listen for SIGTERM: {}", err),
         }
     }
 
-    // Await all spawned tasks
-    try_join_all(tasks).await.map(|_| ()).map_err(|e| e.into())
+    let _ = signal::ctrl_c().await;
 }
 
 struct Server {
     peers: Arc<Peers>,
     nodes: Nodes,
     routing: Routing,
+    config_file: PathBuf,
+    config: RwLock<Config>,
+    stats: Stats,
+    events: Events,
+    rate_limiter: Option<RateLimiter>,
+    asymmetric_links: AsymmetricLinks,
+    debug_trace: DebugTrace,
+    /// Source of the current time for all the timeouts
+    clock: Arc<dyn Clock>,
+    /// Stops accepting announcements and servicing the local router when triggered
+    shutdown: Shutdown,
+    /// Notified by the local router service task once it has unregistered from the router
+    service_stopped: Notify,
     mut_state: Mutex<ServerMut>,
 }
 
 struct ServerMut {
-    debug_node: Option<CJDNS_IP6>,
     self_node: Option<Arc<Node>>,
     current_node: Option<CJDNS_IP6>,
 }
@@ -123,16 +237,28 @@ enum ReplyError {
     NoEncodingScheme,
     NoVersion,
     UnknownNode,
+    RateLimited,
+    DeniedByPolicy,
 }
 
 impl Server {
-    fn new(peers: Arc<Peers>) -> Self {
+    fn new(peers: Arc<Peers>, clock: Arc<dyn Clock>, config_file: PathBuf, config: Config) -> Self {
+        let rate_limiter = config.ann_rate_limit.clone().map(|limits| RateLimiter::new(limits, clock.instant()));
         Server {
             peers: peers.clone(),
             nodes: Nodes::new(peers),
             routing: Routing::new(),
+            config_file,
+            config: RwLock::new(config),
+            stats: Stats::new(),
+            events: Events::new(),
+            rate_limiter,
+            asymmetric_links: AsymmetricLinks::new(),
+            debug_trace: DebugTrace::new(),
+            clock,
+            shutdown: Shutdown::new(),
+            service_stopped: Notify::new(),
             mut_state: Mutex::new(ServerMut {
-                debug_node: None,
                 self_node: None,
                 current_node: None,
             }),
@@ -147,10 +273,44 @@ const MAX_GLOBAL_CLOCKSKEW: Duration = Duration::from_secs(60 * 60 * 20);
 const GLOBAL_TIMEOUT: Duration = Duration::from_secs(MAX_GLOBAL_CLOCKSKEW.as_secs() + AGREED_TIMEOUT.as_secs());
 
 impl Server {
+    /// Forget nodes which haven't announced for too long.
+    fn forget_old_nodes(&self) {
+        for node in self.nodes.keep_table_clean(self.clock.now()) {
+            self.routing.links_changed(&node);
+            self.events.emit(TopologyEvent::NodeForgotten { node });
+        }
+    }
+
+    /// Wind down before exiting: stop accepting announcements, unregister from the local router (if connected to one)
+    /// and close the peer connections.
+    async fn shut_down(&self, connected_to_router: bool) {
+        self.shutdown.trigger();
+        let service_stopped = async {
+            if connected_to_router && time::timeout(SHUTDOWN_TIMEOUT, self.service_stopped.notified()).await.is_err() {
+                warn!("Local router service didn't stop in time");
+            }
+        };
+        futures::future::join(service_stopped, self.peers.close_all(SHUTDOWN_TIMEOUT)).await;
+    }
+
+    /// Record a line to the trace of the node being debugged, and to the log at debug level.
+    fn trace_node(&self, ip: &CJDNS_IP6, text: String) {
+        debug!("[{}] {}", ip, text);
+        self.debug_trace.add(ip, self.clock.instant(), make_timestamp(self.clock.now()), text);
+    }
+
     async fn handle_announce(&self, announce: AnnData, from_node: bool) {
+        if self.shutdown.is_triggered() {
+            debug!("Shutting down, announcement dropped");
+            return;
+        }
         let res = self.handle_announce_impl(announce, from_node, None).await;
-        if let Err(err) = res {
-            warn!("Bad announcement: {}", err);
+        match res {
+            Ok((_, reply_err)) => self.stats.ann_processed(&reply_err),
+            Err(err) => {
+                self.stats.ann_failed();
+                warn!("Bad announcement: {}", err);
+            }
         }
     }
 
@@ -175,22 +335,25 @@ impl Server {
             self_node = {
                 let mut state = self.mut_state.lock();
                 state.current_node = Some(ann.node_ip.clone());
-                if maybe_debug_noisy.is_none() {
-                    debug_noisy = state.debug_node.as_ref().map(|dn| dn.eq(&ann.node_ip)).unwrap_or(false);
-                }
                 state.self_node.as_ref().map(|n| n.clone())
             };
+            if maybe_debug_noisy.is_none() {
+                debug_noisy = self.debug_trace.is_traced(&ann.node_ip, self.clock.instant());
+            }
             node = self.nodes.by_ip(&ann.node_ip);
-            if log_enabled!(log::Level::Debug) && debug_noisy {
-                debug!(
-                    "ANN from [{}] ts [{}] isReset [{}] peers [{}] ls [{}] known [{}]{}",
-                    ann.node_ip,
-                    ann.header.timestamp,
-                    ann.header.is_reset,
-                    ann.entities.iter().filter(|&a| matches!(&a, Entity::Peer{..})).count(),
-                    ann.entities.iter().filter(|&a| matches!(&a, Entity::LinkState{..})).count(),
-                    node.is_some(),
-                    if node.is_none() && !ann.header.is_reset { " ERR_UNKNOWN" } else { "" }
+            if debug_noisy {
+                self.trace_node(
+                    &ann.node_ip,
+                    format!(
+                        "ANN from [{}] ts [{}] isReset [{}] peers [{}] ls [{}] known [{}]{}",
+                        ann.node_ip,
+                        ann.header.timestamp,
+                        ann.header.is_reset,
+                        ann.entities.iter().filter(|&a| matches!(&a, Entity::Peer{..})).count(),
+                        ann.entities.iter().filter(|&a| matches!(&a, Entity::LinkState{..})).count(),
+                        node.is_some(),
+                        if node.is_none() && !ann.header.is_reset { " ERR_UNKNOWN" } else { "" }
+                    ),
                 );
             }
         }
@@ -215,7 +378,7 @@ impl Server {
                 }
             }
             if let Some(ann) = ann_opt.as_ref() {
-                let clock_skew = time_diff(SystemTime::now(), mktime(ann.header.timestamp));
+                let clock_skew = time_diff(self.clock.now(), mktime(ann.header.timestamp));
                 if clock_skew > MAX_CLOCKSKEW {
                     warn!("unacceptably large clock skew {}h", clock_skew.as_secs_f64() / 60.0 / 60.0);
                     reply_error = ReplyError::ExcessiveClockSkew;
@@ -264,6 +427,20 @@ impl Server {
             }
         };
 
+        if let Some(ann) = ann_opt.as_ref() {
+            let accepted = match self.config.read().ann_policy.as_ref() {
+                Some(policy) => policy.accepts(&ann.node_pub_key, &ann.node_ip, version),
+                None => true,
+            };
+            if !accepted {
+                if debug_noisy {
+                    self.trace_node(&ann.node_ip, format!("announcement from {} denied by policy", ann.node_ip));
+                }
+                reply_error = ReplyError::DeniedByPolicy;
+                ann_opt = None;
+            }
+        }
+
         let ann = {
             if let Some(ann) = ann_opt {
                 ann
@@ -283,6 +460,7 @@ impl Server {
         }
 
         if ann.header.is_reset {
+            let is_new_node = node.is_none();
             let n = self.nodes.new_node(
                 version.unwrap(),
                 ann.node_pub_key.clone(),
@@ -292,7 +470,15 @@ impl Server {
                 Some(ann.clone()),
             )?;
             let try_node = self.nodes.add_node(n, true);
-            node = Some(try_node.map_err(|()| anyhow!("internal error: add_node() failed"))?);
+            let new_node = try_node.map_err(|()| anyhow!("internal error: add_node() failed"))?;
+            let (node_ip, version, key) = (new_node.ipv6.clone(), new_node.version, new_node.key.to_string());
+            self.events.emit(if is_new_node {
+                TopologyEvent::NodeAdded { node: node_ip, version, key }
+            } else {
+                TopologyEvent::NodeReset { node: node_ip, version, key }
+            });
+            self.routing.links_changed(&new_node.ipv6);
+            node = Some(new_node);
         } else if let Some(node) = node.as_ref() {
             self.add_announcement(node.clone(), &ann, debug_noisy);
         } else {
@@ -303,12 +489,52 @@ impl Server {
 
         let node = node.expect("internal error: node expected"); // Due to the above checks it should be valid node here
 
-        'peer: for peer in utils::peers_from_announcement(&ann) {
+        self.update_peer_links(&node, &ann, debug_noisy);
+
+        self.link_state_update1(&ann, node.clone(), debug_noisy);
+        self.routing.link_states_changed(&node.ipv6);
+
+        let has_ann = {
+            let node_mut = node.mut_state.read();
+            node_mut.announcements.iter().any(|a| *a == ann) || node_mut.reset_msg.as_ref().map(|reset_msg| *reset_msg == ann).unwrap_or(false)
+        };
+        if has_ann {
+            self.peers.add_ann(ann.hash.clone(), ann.binary.clone()).await;
+        }
+
+        return Ok((hash::node_announcement_hash(Some(node), debug_noisy), reply_error));
+    }
+
+    /// Store the links announced by the node, or remove the withdrawn ones.
+    fn update_peer_links(&self, node: &Arc<Node>, ann: &Announcement, debug_noisy: bool) {
+        let policy = self.config.read().ann_policy.clone();
+
+        'peer: for peer in utils::peers_from_announcement(ann) {
+            // Links to the nodes outside of the accepted sub-network are dropped just like announcements from them
+            if let Some(policy) = policy.as_ref().filter(|_| peer.label.is_some()) {
+                let far_end = self.nodes.by_ip(&peer.ipv6);
+                if !policy.accepts_peer(&peer.ipv6, far_end.as_ref().map(|n| (&n.key, n.version))) {
+                    if debug_noisy {
+                        self.trace_node(&node.ipv6, format!("link to {} denied by policy", peer.ipv6));
+                    }
+                    continue 'peer;
+                }
+            }
+
             let mut inward_links_by_ip = node.inward_links_by_ip.lock();
 
             if peer.label.is_none() {
                 if let Some(links) = inward_links_by_ip.get_mut(&peer.ipv6) {
+                    let links_before = links.len();
                     links.retain(|l| l.peer_num != peer.peer_num);
+                    if links.len() < links_before {
+                        self.routing.links_changed(&node.ipv6);
+                        self.events.emit(TopologyEvent::LinkWithdrawn {
+                            node: node.ipv6.clone(),
+                            peer: peer.ipv6.clone(),
+                            peer_num: peer.peer_num,
+                        });
+                    }
                     if links.is_empty() {
                         inward_links_by_ip.remove(&peer.ipv6);
                     }
@@ -319,7 +545,13 @@ impl Server {
             }
 
             let stored = inward_links_by_ip.get_mut(&peer.ipv6);
-            let new_link = mk_link(peer, &ann);
+            let new_link = mk_link(peer, ann);
+            let link_added_event = TopologyEvent::LinkAdded {
+                node: node.ipv6.clone(),
+                peer: peer.ipv6.clone(),
+                peer_num: new_link.peer_num,
+                label: new_link.label,
+            };
 
             if let Some(stored) = stored {
                 'link: for stored_link in stored.iter_mut() {
@@ -341,27 +573,21 @@ impl Server {
                     }
                     // major changes, replace the link and wipe out link state
                     *stored_link = new_link;
+                    self.routing.links_changed(&node.ipv6);
+                    self.events.emit(link_added_event);
                     continue 'peer;
                 }
                 // We get here when there is no match
                 stored.push(new_link);
+                self.routing.links_changed(&node.ipv6);
+                self.events.emit(link_added_event);
             } else {
                 inward_links_by_ip.insert(peer.ipv6.clone(), vec![new_link]);
+                self.routing.links_changed(&node.ipv6);
+                self.events.emit(link_added_event);
                 continue 'peer;
             }
         }
-
-        self.link_state_update1(&ann, node.clone(), debug_noisy);
-
-        let has_ann = {
-            let node_mut = node.mut_state.read();
-            node_mut.announcements.iter().any(|a| *a == ann) || node_mut.reset_msg.as_ref().map(|reset_msg| *reset_msg == ann).unwrap_or(false)
-        };
-        if has_ann {
-            self.peers.add_ann(ann.hash.clone(), ann.binary.clone()).await;
-        }
-
-        return Ok((hash::node_announcement_hash(Some(node), debug_noisy), reply_error));
     }
 
     fn add_announcement(&self, node: Arc<Node>, ann: &Announcement, debug_noisy: bool) {
@@ -374,7 +600,7 @@ impl Server {
         node_mut.announcements.retain(|a| {
             if mktime(a.header.timestamp) < since_time {
                 if debug_noisy {
-                    debug!("Expiring ann [{}] because it is too old", utils::ann_id(a));
+                    self.trace_node(&node.ipv6, format!("Expiring ann [{}] because it is too old", utils::ann_id(a)));
                 }
                 drop_announce.push(a.clone());
                 return false;
@@ -399,20 +625,26 @@ impl Server {
             if safe || *a == *ann {
                 if *a == *ann {
                     if debug_noisy {
-                        debug!("Keeping ann [{}] because it was announced just now", utils::ann_id(a));
+                        self.trace_node(&node.ipv6, format!("Keeping ann [{}] because it was announced just now", utils::ann_id(a)));
                     }
                 } else {
                     if debug_noisy {
-                        debug!("Keeping ann [{}] for entities [{}]", utils::ann_id(a), debug::print_entities(&justifications));
+                        self.trace_node(
+                            &node.ipv6,
+                            format!("Keeping ann [{}] for entities [{}]", utils::ann_id(a), debug::print_entities(&justifications)),
+                        );
                     }
                 }
                 return true;
             } else {
                 if debug_noisy {
-                    debug!(
-                        "Dropping ann [{}] because all entities [{}] have been re-announced",
-                        utils::ann_id(a),
-                        debug::print_entities(&justifications)
+                    self.trace_node(
+                        &node.ipv6,
+                        format!(
+                            "Dropping ann [{}] because all entities [{}] have been re-announced",
+                            utils::ann_id(a),
+                            debug::print_entities(&justifications)
+                        ),
                     );
                 }
                 drop_announce.push(a.clone());
@@ -421,7 +653,7 @@ impl Server {
         });
 
         if debug_noisy {
-            debug!("Finally there are {} anns in the state", node_mut.announcements.len());
+            self.trace_node(&node.ipv6, format!("Finally there are {} anns in the state", node_mut.announcements.len()));
         }
         for a in drop_announce {
             if node_mut.reset_msg.as_ref().map(|reset_msg| a != *reset_msg).unwrap_or(true) {
@@ -434,8 +666,9 @@ impl Server {
     fn link_state_update1(&self, ann: &Announcement, node: Arc<Node>, debug_noisy: bool) {
         let time = ann.header.timestamp;
         let ts = time / 1000 / 10;
-        // Timeslots older than AGREED_TIMEOUT will be dropped
-        let earliest_ok_ts = ts - (AGREED_TIMEOUT.as_millis() as u64 / 1000 / 10);
+        // Timeslots older than the configured retention (but at least AGREED_TIMEOUT) will be dropped
+        let retention = Duration::from_secs(self.config.read().link_state_retention).max(AGREED_TIMEOUT);
+        let earliest_ok_ts = ts.saturating_sub(retention.as_secs() / Link::TIMESLOT_SECONDS);
 
         let mut inward_links_by_num = HashMap::<u16, Link>::new();
         let mut ips_by_num = HashMap::<u16, CJDNS_IP6>::new();
@@ -449,16 +682,9 @@ impl Server {
 
         for ls in utils::link_states_from_announcement(ann) {
             if let Some(link) = inward_links_by_num.get(&ls.node_id) {
+                link.expire_history(earliest_ok_ts);
                 let mut link_state = link.link_state.lock();
 
-                link_state.retain(|&old_ls, _| {
-                    let drop = old_ls < earliest_ok_ts;
-                    //if drop {
-                    //    warn!("dropping link state slot {} from {} for age", old_ls, ann.node_ip);
-                    //}
-                    !drop
-                });
-
                 {
                     let mut link_mut = link.mut_state.lock();
                     if link_mut.most_recent_ls_slot > ts {
@@ -504,7 +730,10 @@ impl Server {
                         assert!(delta_v >= 0.0);
                         link.mut_state.lock().value += delta_v;
                         if debug_noisy {
-                            debug!("LSU {} <- {}/{} : {:?}", ann.node_ip, ips_by_num[&ls.node_id], ls.node_id, new_state);
+                            self.trace_node(
+                                &ann.node_ip,
+                                format!("LSU {} <- {}/{} : {:?}", ann.node_ip, ips_by_num[&ls.node_id], ls.node_id, new_state),
+                            );
                         }
                         link_state.insert(time, new_state);
                         time -= 1;
@@ -512,6 +741,16 @@ impl Server {
 
                     index -= 1;
                 }
+
+                let value = link.mut_state.lock().value;
+                link.value_history.lock().insert(ts, value);
+
+                self.events.emit(TopologyEvent::LinkStateUpdated {
+                    node: node.ipv6.clone(),
+                    peer: ips_by_num[&ls.node_id].clone(),
+                    peer_num: ls.node_id,
+                    value,
+                });
             }
         }
     }
@@ -528,6 +767,8 @@ impl std::fmt::Display for ReplyError {
             ReplyError::NoEncodingScheme => write!(f, "no_encodingScheme"),
             ReplyError::NoVersion => write!(f, "no_version"),
             ReplyError::UnknownNode => write!(f, "unknown_node"),
+            ReplyError::RateLimited => write!(f, "rate_limited"),
+            ReplyError::DeniedByPolicy => write!(f, "denied_by_policy"),
         }
     }
 }
@@ -544,3 +785,210 @@ mod debug {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use cjdns_ann::{AnnHash, Announcement, AnnouncementHeader, Entity, LinkStateData, PeerData};
+    use cjdns_core::{schemes, RoutingLabel};
+    use cjdns_keys::{CJDNSPublicKey, CJDNS_IP6};
+
+    use crate::config::{AnnPolicyConfig, Config};
+    use crate::peer::create_peers;
+    use crate::utils::clock::ManualClock;
+    use crate::utils::timestamp::make_timestamp;
+
+    use super::link::{mk_link, Link, LinkStateEntry};
+    use super::nodes::Node;
+    use super::{Server, AGREED_TIMEOUT, GLOBAL_TIMEOUT};
+
+    fn start_time() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    }
+
+    pub(super) fn test_server(clock: Arc<ManualClock>) -> Server {
+        let (peers, _) = create_peers(clock.clone());
+        Server::new(Arc::new(peers), clock, PathBuf::new(), Config::default())
+    }
+
+    fn add_test_node(server: &Server, timestamp: SystemTime) -> Arc<Node> {
+        let key = CJDNSPublicKey::try_from("z15pzyd9wgzs2g5np7d3swrqc1533yb7xx9dq0pvrqrqs42uwgq0.k").expect("bad test key");
+        let ip = CJDNS_IP6::try_from(&key).expect("bad test key");
+        let scheme = Some(Arc::new(schemes::V358.clone()));
+        let node = server.nodes.new_node(21, key, scheme, timestamp, ip, None).expect("new_node");
+        server.nodes.add_node(node, false).expect("add_node")
+    }
+
+    /// Node `fc00::<n>` of the given protocol version, without any links.
+    pub(super) fn add_numbered_node(server: &Server, n: u16, version: u16) -> Arc<Node> {
+        let key = CJDNSPublicKey::try_from("z15pzyd9wgzs2g5np7d3swrqc1533yb7xx9dq0pvrqrqs42uwgq0.k").expect("bad test key");
+        let ip = CJDNS_IP6::try_from(format!("fc00:0000:0000:0000:0000:0000:0000:{:04x}", n).as_str()).expect("bad test ip");
+        let scheme = Some(Arc::new(schemes::V358.clone()));
+        let node = server.nodes.new_node(version, key, scheme, start_time(), ip, None).expect("new_node");
+        server.nodes.add_node(node, false).expect("add_node")
+    }
+
+    /// Links in both directions between the nodes, as if each of them announced the other one as its peer.
+    pub(super) fn link_nodes(server: &Server, a: &Node, b: &Node) {
+        for &(node, peer) in [(a, b), (b, a)].iter() {
+            let peer = PeerData {
+                ipv6: peer.ipv6.clone(),
+                ..peer_data(0)
+            };
+            let link = mk_link(&peer, &test_ann(node, start_time(), Vec::new(), 0));
+            node.inward_links_by_ip.lock().insert(peer.ipv6, vec![link]);
+            server.routing.links_changed(&node.ipv6);
+        }
+    }
+
+    /// Announcement by the node, `n` makes it distinct from the others.
+    fn test_ann(node: &Node, timestamp: SystemTime, entities: Vec<Entity>, n: u8) -> Announcement {
+        Announcement {
+            header: AnnouncementHeader {
+                signature: String::new(),
+                pub_signing_key: String::new(),
+                snode_ip: node.ipv6.clone(),
+                version: 1,
+                is_reset: false,
+                timestamp: make_timestamp(timestamp),
+            },
+            entities,
+            node_pub_key: node.key.clone(),
+            node_ip: node.ipv6.clone(),
+            binary: vec![n],
+            hash: AnnHash(vec![n]),
+        }
+    }
+
+    fn peer_data(peer_num: u16) -> PeerData {
+        PeerData {
+            ipv6: CJDNS_IP6::try_from("fc00:0000:0000:0000:0000:0000:0000:0001").expect("bad test ip"),
+            label: RoutingLabel::try_new(0x13),
+            mtu: 1280,
+            peer_num,
+            unused: 0,
+            encoding_form_number: 0,
+            flags: 0,
+        }
+    }
+
+    /// Link state of the peer with the given samples, the newest first.
+    fn link_state(peer_num: u16, samples: &[LinkStateEntry]) -> Entity {
+        let mut ls = LinkStateData {
+            node_id: peer_num,
+            starting_point: 0,
+            lag_slots: Default::default(),
+            drop_slots: Default::default(),
+            kb_recv_slots: Default::default(),
+        };
+        for (i, sample) in samples.iter().enumerate() {
+            let slot = ls.lag_slots.len() - 1 - i;
+            ls.lag_slots[slot] = Some(sample.lag);
+            ls.drop_slots[slot] = Some(sample.drops);
+            ls.kb_recv_slots[slot] = Some(sample.kb_recv);
+        }
+        Entity::LinkState(ls)
+    }
+
+    #[test]
+    fn test_node_expiry() {
+        let clock = Arc::new(ManualClock::new(start_time()));
+        let server = test_server(clock.clone());
+        let node = add_test_node(&server, start_time());
+
+        clock.advance(GLOBAL_TIMEOUT);
+        server.forget_old_nodes();
+        assert!(server.nodes.by_ip(&node.ipv6).is_some());
+
+        clock.advance(Duration::from_secs(1));
+        server.forget_old_nodes();
+        assert!(server.nodes.by_ip(&node.ipv6).is_none());
+    }
+
+    #[test]
+    fn test_announcement_expiry() {
+        let t0 = start_time();
+        let clock = Arc::new(ManualClock::new(t0));
+        let server = test_server(clock);
+        let node = add_test_node(&server, t0);
+        let ann_ids = || node.mut_state.read().announcements.iter().map(|a| a.binary[0]).collect::<Vec<_>>();
+
+        let minute = Duration::from_secs(60);
+        let a1 = test_ann(&node, t0, vec![Entity::Peer(peer_data(1))], 1);
+        let a2 = test_ann(&node, t0 + minute, vec![Entity::Peer(peer_data(2))], 2);
+        let a3 = test_ann(&node, t0 + 2 * minute, vec![Entity::Peer(peer_data(2))], 3);
+        server.add_announcement(node.clone(), &a1, false);
+        server.add_announcement(node.clone(), &a2, false);
+        assert_eq!(ann_ids(), vec![2, 1]);
+
+        // Peer 2 is re-announced, so the older announcement of it is no longer needed
+        server.add_announcement(node.clone(), &a3, false);
+        assert_eq!(ann_ids(), vec![3, 1]);
+
+        // Announcements older than AGREED_TIMEOUT expire even if nothing replaced them
+        let a4 = test_ann(&node, t0 + AGREED_TIMEOUT + Duration::from_secs(1), Vec::new(), 4);
+        server.add_announcement(node.clone(), &a4, false);
+        assert_eq!(ann_ids(), vec![4, 3]);
+        assert_eq!(node.mut_state.read().timestamp, t0 + AGREED_TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_link_state_decay() {
+        let t0 = start_time();
+        let clock = Arc::new(ManualClock::new(t0));
+        let server = test_server(clock);
+        let node = add_test_node(&server, t0);
+
+        let peer = peer_data(1);
+        let link = mk_link(&peer, &test_ann(&node, t0, vec![Entity::Peer(peer.clone())], 1));
+        node.inward_links_by_ip.lock().insert(peer.ipv6.clone(), vec![link.clone()]);
+
+        let sample = LinkStateEntry {
+            drops: 0,
+            lag: 100,
+            kb_recv: 1000,
+        };
+        let v = sample.ls_value();
+        assert!(v > 0.0);
+
+        // The newest sample counts in full, the one a timeslot before it is decayed
+        let ann = test_ann(&node, t0, vec![link_state(1, &[sample.clone(), sample.clone()])], 2);
+        server.link_state_update1(&ann, node.clone(), false);
+        let expected = v + v / (1.0 + Link::DECAY_PER_TIMESLOT);
+        assert!((link.mut_state.lock().value - expected).abs() < 1e-9);
+
+        // Link state announced 6 timeslots behind the most recent one decays the value accumulated so far
+        let late = t0 - Duration::from_secs(6 * Link::TIMESLOT_SECONDS);
+        let ann = test_ann(&node, late, vec![link_state(1, std::slice::from_ref(&sample))], 3);
+        server.link_state_update1(&ann, node.clone(), false);
+        let expected = expected / (1.0 + 6.0 * Link::DECAY_PER_TIMESLOT) + v;
+        assert!((link.mut_state.lock().value - expected).abs() < 1e-9);
+    }
+    #[test]
+    fn test_peer_links_policy() {
+        let t0 = start_time();
+        let clock = Arc::new(ManualClock::new(t0));
+        let server = test_server(clock);
+        let node = add_test_node(&server, t0);
+
+        let denied = peer_data(1);
+        let allowed = PeerData {
+            ipv6: CJDNS_IP6::try_from("fc00:0000:0000:0000:0000:0000:0001:0001").expect("bad test ip"),
+            ..peer_data(2)
+        };
+        server.config.write().ann_policy = Some(AnnPolicyConfig {
+            deny_prefixes: vec![format!("{}/112", denied.ipv6).parse().expect("bad test prefix")],
+            ..AnnPolicyConfig::default()
+        });
+
+        let ann = test_ann(&node, t0, vec![Entity::Peer(denied.clone()), Entity::Peer(allowed.clone())], 1);
+        server.update_peer_links(&node, &ann, false);
+        let links = node.inward_links_by_ip.lock();
+        assert!(links.get(&denied.ipv6).is_none());
+        assert_eq!(links.get(&allowed.ipv6).map(Vec::len), Some(1));
+    }
+}
@@ -0,0 +1,255 @@
+//! Network graph analysis
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Undirected graph without loops and parallel links.
+pub(super) struct UndirectedGraph<T> {
+    tags: Vec<T>,
+    index: HashMap<T, usize>,
+    adj: Vec<Vec<usize>>,
+}
+
+impl<T> UndirectedGraph<T>
+where
+    T: Clone + Eq + Hash + Ord,
+{
+    /// Number of breadth-first sweeps used to estimate the diameter of each component.
+    const DIAMETER_SWEEPS: usize = 4;
+
+    pub(super) fn new() -> Self {
+        UndirectedGraph {
+            tags: Vec::new(),
+            index: HashMap::new(),
+            adj: Vec::new(),
+        }
+    }
+
+    /// Add node if it is not in the graph yet, returning its index.
+    pub(super) fn add_node(&mut self, tag: T) -> usize {
+        if let Some(&i) = self.index.get(&tag) {
+            return i;
+        }
+        let i = self.tags.len();
+        self.tags.push(tag.clone());
+        self.index.insert(tag, i);
+        self.adj.push(Vec::new());
+        i
+    }
+
+    /// Add link between the nodes, adding the nodes as well. Loops and duplicate links are ignored.
+    pub(super) fn add_link(&mut self, a: T, b: T) {
+        let (a, b) = (self.add_node(a), self.add_node(b));
+        if a == b || self.adj[a].contains(&b) {
+            return;
+        }
+        self.adj[a].push(b);
+        self.adj[b].push(a);
+    }
+
+    pub(super) fn node_count(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub(super) fn link_count(&self) -> usize {
+        self.adj.iter().map(Vec::len).sum::<usize>() / 2
+    }
+
+    /// Connected components, the largest first.
+    pub(super) fn components(&self) -> Vec<Vec<T>> {
+        // Distances are never reset, so each node is visited once, by the search from the first node of its component
+        let mut distances = vec![None; self.tags.len()];
+        let mut components = Vec::new();
+        for start in 0..self.tags.len() {
+            if distances[start].is_some() {
+                continue;
+            }
+            let mut members = self.bfs(start, &mut distances).into_iter().map(|i| self.tags[i].clone()).collect::<Vec<_>>();
+            members.sort();
+            components.push(members);
+        }
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        components
+    }
+
+    /// Number of nodes having each degree.
+    pub(super) fn degree_distribution(&self) -> BTreeMap<usize, usize> {
+        let mut distribution = BTreeMap::new();
+        for links in self.adj.iter() {
+            *distribution.entry(links.len()).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    /// Articulation points (nodes whose removal splits their component)
+    /// and bridges (links whose removal splits their component), both sorted.
+    pub(super) fn cut_points(&self) -> (Vec<T>, Vec<(T, T)>) {
+        const UNVISITED: usize = usize::MAX;
+        let n = self.tags.len();
+        let mut disc = vec![UNVISITED; n];
+        let mut low = vec![0; n];
+        let mut is_articulation = vec![false; n];
+        let mut bridges = Vec::new();
+        let mut timer = 0;
+
+        // Tarjan's algorithm with explicit stack, since the graph may be too deep for recursion
+        for root in 0..n {
+            if disc[root] != UNVISITED {
+                continue;
+            }
+            disc[root] = timer;
+            low[root] = timer;
+            timer += 1;
+            let mut root_children = 0;
+            // Node, its DFS parent and index of the next neighbor to visit
+            let mut stack = vec![(root, None, 0)];
+
+            while let Some(&mut (v, parent, ref mut next)) = stack.last_mut() {
+                if let Some(&w) = self.adj[v].get(*next) {
+                    *next += 1;
+                    if Some(w) == parent {
+                        continue;
+                    }
+                    if disc[w] == UNVISITED {
+                        disc[w] = timer;
+                        low[w] = timer;
+                        timer += 1;
+                        if v == root {
+                            root_children += 1;
+                        }
+                        stack.push((w, Some(v), 0));
+                    } else {
+                        low[v] = low[v].min(disc[w]);
+                    }
+                } else {
+                    stack.pop();
+                    if let Some(p) = parent {
+                        low[p] = low[p].min(low[v]);
+                        if low[v] > disc[p] {
+                            bridges.push(self.ordered_pair(p, v));
+                        }
+                        if p != root && low[v] >= disc[p] {
+                            is_articulation[p] = true;
+                        }
+                    }
+                }
+            }
+
+            if root_children > 1 {
+                is_articulation[root] = true;
+            }
+        }
+
+        let mut articulation_points = (0..n).filter(|&i| is_articulation[i]).map(|i| self.tags[i].clone()).collect::<Vec<_>>();
+        articulation_points.sort();
+        bridges.sort();
+        (articulation_points, bridges)
+    }
+
+    /// Lower bound of the diameter (the longest shortest path in hops) over all components,
+    /// estimated by a few breadth-first sweeps each starting from the farthest node found by the previous one.
+    /// It is exact for trees and usually is for real networks.
+    pub(super) fn approx_diameter(&self) -> usize {
+        let mut diameter = 0;
+        let mut visited = vec![false; self.tags.len()];
+        let mut distances = vec![None; self.tags.len()];
+        for start in 0..self.tags.len() {
+            if visited[start] {
+                continue;
+            }
+            let mut from = start;
+            for sweep in 0..Self::DIAMETER_SWEEPS {
+                let reached = self.bfs(from, &mut distances);
+                if sweep == 0 {
+                    reached.iter().for_each(|&i| visited[i] = true);
+                }
+                let (farthest, eccentricity) = reached
+                    .iter()
+                    .map(|&i| (i, distances[i].expect("reached node has distance")))
+                    .max_by_key(|&(i, d)| (d, std::cmp::Reverse(i)))
+                    .expect("start node is always reachable");
+                // Only the reached nodes are reset, so each sweep costs the size of the component
+                reached.iter().for_each(|&i| distances[i] = None);
+                diameter = diameter.max(eccentricity);
+                if farthest == from {
+                    break;
+                }
+                from = farthest;
+            }
+        }
+        diameter
+    }
+
+    /// Nodes reachable from the given one, including itself, sorted. Empty if there is no such node.
+    pub(super) fn reachable_from(&self, tag: &T) -> Vec<T> {
+        let start = match self.index.get(tag) {
+            Some(&start) => start,
+            None => return Vec::new(),
+        };
+        let mut distances = vec![None; self.tags.len()];
+        let mut reachable = self.bfs(start, &mut distances).into_iter().map(|i| self.tags[i].clone()).collect::<Vec<_>>();
+        reachable.sort();
+        reachable
+    }
+
+    /// Set the hop count from the start node to every node it reaches, returning the reached nodes (the start one first).
+    /// Nodes with a hop count already set are treated as visited, so the caller resets the reached ones to search again.
+    fn bfs(&self, start: usize, distances: &mut [Option<usize>]) -> Vec<usize> {
+        let mut reached = Vec::new();
+        let mut queue = VecDeque::new();
+        distances[start] = Some(0);
+        queue.push_back(start);
+        while let Some(v) = queue.pop_front() {
+            reached.push(v);
+            let d = distances[v].expect("queued node has distance");
+            for &w in self.adj[v].iter() {
+                if distances[w].is_none() {
+                    distances[w] = Some(d + 1);
+                    queue.push_back(w);
+                }
+            }
+        }
+        reached
+    }
+
+    fn ordered_pair(&self, a: usize, b: usize) -> (T, T) {
+        let (a, b) = (self.tags[a].clone(), self.tags[b].clone());
+        if a <= b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+#[test]
+fn test_graph_analysis() {
+    let mut g = UndirectedGraph::new();
+    // Triangle A-B-C with a tail C-D-E, separate pair F-G and isolated H
+    for &(a, b) in [("A", "B"), ("B", "C"), ("C", "A"), ("C", "D"), ("D", "E"), ("F", "G"), ("B", "A"), ("E", "E")].iter() {
+        g.add_link(a, b);
+    }
+    g.add_node("H");
+
+    assert_eq!(g.node_count(), 8);
+    assert_eq!(g.link_count(), 6);
+    assert_eq!(g.components(), vec![vec!["A", "B", "C", "D", "E"], vec!["F", "G"], vec!["H"]]);
+    assert_eq!(g.degree_distribution(), vec![(0, 1), (1, 3), (2, 3), (3, 1)].into_iter().collect());
+
+    let (articulation_points, bridges) = g.cut_points();
+    assert_eq!(articulation_points, vec!["C", "D"]);
+    assert_eq!(bridges, vec![("C", "D"), ("D", "E"), ("F", "G")]);
+
+    assert_eq!(g.approx_diameter(), 3);
+    assert_eq!(g.reachable_from(&"D"), vec!["A", "B", "C", "D", "E"]);
+    assert_eq!(g.reachable_from(&"H"), vec!["H"]);
+    assert!(g.reachable_from(&"X").is_empty());
+
+    // Root of the DFS tree is an articulation point only if it has several children
+    let mut star = UndirectedGraph::new();
+    star.add_link(1, 2);
+    star.add_link(1, 3);
+    star.add_link(1, 4);
+    assert_eq!(star.cut_points(), (vec![1], vec![(1, 2), (1, 3), (1, 4)]));
+    assert_eq!(star.approx_diameter(), 2);
+}
@@ -0,0 +1,115 @@
+//! Tracking of links announced in one direction only
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use cjdns_keys::CJDNS_IP6;
+
+use crate::server::nodes::Nodes;
+
+/// Half-links found in the node table, with the time each was first seen.
+pub(super) struct AsymmetricLinks {
+    first_seen: Mutex<HashMap<(CJDNS_IP6, CJDNS_IP6), Instant>>,
+}
+
+/// Link announced by the node as its inward link from the peer,
+/// while the peer doesn't announce a link from the node. Such links are not used for routing.
+pub(super) struct AsymmetricLink {
+    pub(super) node: CJDNS_IP6,
+    pub(super) peer: CJDNS_IP6,
+    /// Whether the peer is in the node table at all
+    pub(super) peer_known: bool,
+    /// How long the link has been asymmetric (since it was first seen so)
+    pub(super) duration: Duration,
+}
+
+impl AsymmetricLinks {
+    pub(super) fn new() -> Self {
+        AsymmetricLinks {
+            first_seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Scan the node table for half-links. Returns them, the longest lasting first.
+    pub(super) fn update(&self, nodes: &Nodes, now: Instant) -> Vec<AsymmetricLink> {
+        self.track(find_half_links(nodes), now)
+    }
+
+    /// Remember when each of the given half-links was first seen, forgetting the ones no longer present.
+    fn track(&self, half_links: Vec<(CJDNS_IP6, CJDNS_IP6, bool)>, now: Instant) -> Vec<AsymmetricLink> {
+        let mut first_seen = self.first_seen.lock();
+        let mut still_seen = HashMap::with_capacity(half_links.len());
+        let mut res = Vec::with_capacity(half_links.len());
+        for (node, peer, peer_known) in half_links {
+            let key = (node, peer);
+            let since = first_seen.get(&key).copied().unwrap_or(now);
+            still_seen.insert(key.clone(), since);
+            let (node, peer) = key;
+            res.push(AsymmetricLink {
+                node,
+                peer,
+                peer_known,
+                duration: now.saturating_duration_since(since),
+            });
+        }
+        *first_seen = still_seen;
+
+        res.sort_by(|a, b| b.duration.cmp(&a.duration).then_with(|| (&a.node, &a.peer).cmp(&(&b.node, &b.peer))));
+        res
+    }
+}
+
+/// Links (as announcing node and its peer) without the link in the opposite direction,
+/// together with whether the peer is known.
+fn find_half_links(nodes: &Nodes) -> Vec<(CJDNS_IP6, CJDNS_IP6, bool)> {
+    let mut res = Vec::new();
+    for ip in nodes.all_ips() {
+        let node = match nodes.by_ip(&ip) {
+            Some(node) => node,
+            None => continue,
+        };
+        let peer_ips = node.inward_links_by_ip.lock().keys().cloned().collect::<Vec<_>>();
+        for peer_ip in peer_ips {
+            match nodes.by_ip(&peer_ip) {
+                Some(peer) => {
+                    if peer.inward_links_by_ip.lock().get(&ip).is_none() {
+                        res.push((ip.clone(), peer_ip, true));
+                    }
+                }
+                None => res.push((ip.clone(), peer_ip, false)),
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn test_asymmetric_links_tracking() {
+    use std::convert::TryFrom;
+
+    let ip = |s: &str| CJDNS_IP6::try_from(s).expect("bad test ip");
+    let a = ip("fc00:0000:0000:0000:0000:0000:0000:0001");
+    let b = ip("fc00:0000:0000:0000:0000:0000:0000:0002");
+    let c = ip("fc00:0000:0000:0000:0000:0000:0000:0003");
+
+    let tracker = AsymmetricLinks::new();
+    let t0 = Instant::now();
+    let t1 = t0 + Duration::from_secs(30);
+    let t2 = t1 + Duration::from_secs(30);
+
+    let res = tracker.track(vec![(a.clone(), b.clone(), true)], t0);
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].duration, Duration::from_secs(0));
+
+    // The old half-link keeps its start time, the new one starts now
+    let res = tracker.track(vec![(a.clone(), c.clone(), false), (a.clone(), b.clone(), true)], t1);
+    assert_eq!(res.iter().map(|l| (&l.node, &l.peer, l.duration.as_secs())).collect::<Vec<_>>(), vec![(&a, &b, 30), (&a, &c, 0)]);
+    assert!(!res[1].peer_known);
+
+    // Once resolved, the half-link is forgotten, so it starts over if it reappears
+    tracker.track(vec![(a.clone(), c.clone(), false)], t2);
+    let res = tracker.track(vec![(a.clone(), b.clone(), true), (a.clone(), c.clone(), false)], t2 + Duration::from_secs(10));
+    assert_eq!(res.iter().map(|l| (&l.peer, l.duration.as_secs())).collect::<Vec<_>>(), vec![(&c, 40), (&b, 0)]);
+}
@@ -0,0 +1,89 @@
+//! Access control for the web server routes
+
+use crate::config::{AuthScope, Config, RouteSet};
+
+/// Scope required to access the route set.
+pub(super) fn required_scope(route_set: RouteSet) -> AuthScope {
+    match route_set {
+        RouteSet::Peer => AuthScope::Peer,
+        RouteSet::Query => AuthScope::Read,
+        RouteSet::Admin => AuthScope::Admin,
+    }
+}
+
+/// Whether the value of the `Authorization` request header grants access to the scope.
+/// The admin scope is always protected, so it is inaccessible unless some admin token is configured.
+/// The read and peer scopes are open until some token granting them (or the peer secret) is configured.
+pub(super) fn is_authorized(config: &Config, scope: AuthScope, authorization: Option<&str>) -> bool {
+    let granting = granting_tokens(config, scope);
+    if scope != AuthScope::Admin && granting.is_empty() {
+        return true;
+    }
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        // Check every token, so the response time doesn't tell which one was close
+        Some(presented) => granting.iter().fold(false, |found, token| constant_time_eq(token, presented) | found),
+        None => false,
+    }
+}
+
+/// All the configured tokens which grant access to the scope.
+fn granting_tokens(config: &Config, scope: AuthScope) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    if scope == AuthScope::Admin {
+        tokens.extend(config.admin_token.as_deref());
+    }
+    if let Some(auth) = config.auth.as_ref() {
+        let scoped = auth.tokens.iter().filter(|token| token.scopes.contains(&scope));
+        tokens.extend(scoped.map(|token| token.token.as_str()));
+        if scope == AuthScope::Peer {
+            tokens.extend(auth.peer_secret.as_deref());
+        }
+    }
+    tokens
+}
+
+/// String comparison taking the same time wherever the strings differ.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[test]
+fn test_is_authorized() {
+    use crate::config::{AuthConfig, AuthTokenConfig};
+
+    let token = |token: &str, scopes: &[AuthScope]| AuthTokenConfig {
+        token: token.to_string(),
+        scopes: scopes.to_vec(),
+    };
+
+    // Without any tokens only the admin scope is closed
+    let config = Config::default();
+    assert!(!is_authorized(&config, AuthScope::Admin, None));
+    assert!(!is_authorized(&config, AuthScope::Admin, Some("Bearer ")));
+    assert!(is_authorized(&config, AuthScope::Read, None));
+    assert!(is_authorized(&config, AuthScope::Peer, None));
+
+    let config = Config {
+        admin_token: Some("legacy".to_string()),
+        auth: Some(AuthConfig {
+            tokens: vec![token("reader", &[AuthScope::Read]), token("operator", &[AuthScope::Admin, AuthScope::Read])],
+            peer_secret: Some("shared".to_string()),
+        }),
+        ..Config::default()
+    };
+    assert!(is_authorized(&config, AuthScope::Admin, Some("Bearer legacy")));
+    assert!(is_authorized(&config, AuthScope::Admin, Some("Bearer operator")));
+    assert!(!is_authorized(&config, AuthScope::Admin, Some("Bearer reader")));
+    assert!(!is_authorized(&config, AuthScope::Admin, Some("operator")));
+    assert!(is_authorized(&config, AuthScope::Read, Some("Bearer reader")));
+    assert!(!is_authorized(&config, AuthScope::Read, Some("Bearer legacy")));
+    assert!(!is_authorized(&config, AuthScope::Read, None));
+    assert!(is_authorized(&config, AuthScope::Peer, Some("Bearer shared")));
+    assert!(!is_authorized(&config, AuthScope::Peer, Some("Bearer shared2")));
+    assert!(!is_authorized(&config, AuthScope::Peer, Some("Bearer reader")));
+
+    assert!(constant_time_eq("abc", "abc"));
+    assert!(!constant_time_eq("abc", "abd"));
+    assert!(!constant_time_eq("abc", "ab"));
+}
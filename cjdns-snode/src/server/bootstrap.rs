@@ -0,0 +1,63 @@
+//! Bootstrapping node table from another supernode's dump
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Error;
+use http::Uri;
+use tokio::fs;
+
+use cjdns_ann::AnnouncementPacket;
+
+use crate::server::persist::parse_anns_dump;
+use crate::server::Server;
+
+/// Load announcements from either `/dump` URL of another supernode or a dump file,
+/// and feed them into the server.
+pub(super) async fn bootstrap_from(server: &Server, source: &str) -> Result<(), Error> {
+    let data = match Uri::from_str(source) {
+        Ok(uri) if uri.scheme_str().is_some() => fetch_dump(uri).await?,
+        _ => read_dump(Path::new(source)).await?,
+    };
+
+    let anns = parse_anns_dump(&data).map_err(|e| anyhow!("failed to parse dump from '{}': {}", source, e))?;
+    let total = anns.len();
+    let anns = anns
+        .into_iter()
+        .filter(|ann| {
+            AnnouncementPacket::try_new(ann.clone())
+                .and_then(|packet| packet.check())
+                .is_ok()
+        })
+        .collect::<Vec<_>>();
+    if anns.len() < total {
+        warn!("Skipped {} announcements with bad signature from '{}'", total - anns.len(), source);
+    }
+
+    let (valid, accepted) = server.replay_anns(anns).await;
+    info!("Bootstrapped {} of {} announcements from '{}'", accepted, valid, source);
+    Ok(())
+}
+
+async fn fetch_dump(uri: Uri) -> Result<Vec<u8>, Error> {
+    if uri.scheme_str() != Some("http") {
+        return Err(anyhow!("unsupported URL scheme in '{}', only http is supported", uri));
+    }
+    info!("Fetching dump from {}", uri);
+    let client = hyper::Client::new();
+    let resp = client.get(uri.clone()).await.map_err(|e| anyhow!("failed to fetch '{}': {}", uri, e))?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("failed to fetch '{}': HTTP status {}", uri, resp.status()));
+    }
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| anyhow!("failed to fetch '{}': {}", uri, e))?;
+    Ok(body.to_vec())
+}
+
+async fn read_dump(file_path: &Path) -> Result<Vec<u8>, Error> {
+    info!("Reading dump from '{}'", file_path.display());
+    fs::read(file_path)
+        .await
+        .map_err(|e| anyhow!("failed to read dump file '{}': {}", file_path.display(), e))
+}
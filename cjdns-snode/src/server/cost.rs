@@ -0,0 +1,150 @@
+//! Link cost models used for routing
+
+use crate::config::CostModel;
+use crate::server::link::{Link, LinkStateEntry};
+
+/// Computes cost of going from a node to its peer.
+pub(super) trait LinkCostModel {
+    /// Cost of the best of the parallel links between two nodes.
+    /// The list of links is never empty. Result must be finite and non-negative.
+    fn link_cost(&self, links: &[Link]) -> f64;
+}
+
+impl CostModel {
+    pub(super) fn model(self) -> &'static dyn LinkCostModel {
+        match self {
+            CostModel::Value => &ValueCost,
+            CostModel::Latency => &LatencyCost,
+            CostModel::HopCount => &HopCountCost,
+            CostModel::DropAverse => &DropAverseCost,
+        }
+    }
+}
+
+impl std::str::FromStr for CostModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|_| format!("unknown cost model '{}'", s))
+    }
+}
+
+struct ValueCost;
+
+impl LinkCostModel for ValueCost {
+    fn link_cost(&self, links: &[Link]) -> f64 {
+        let max_value = links
+            .iter()
+            .map(|link| link.mut_state.lock().value)
+            .fold(0.0, f64::max);
+        let max_value = if max_value == 0.0 { 1e-20 } else { max_value };
+        max_value.recip()
+    }
+}
+
+struct LatencyCost;
+
+impl LatencyCost {
+    /// Assumed latency of a link without link state samples, ms
+    const UNKNOWN_LAG: f64 = 1000.0;
+}
+
+impl LinkCostModel for LatencyCost {
+    fn link_cost(&self, links: &[Link]) -> f64 {
+        links
+            .iter()
+            .map(|link| average(&link.recent_link_states(), |s| s.lag as f64).unwrap_or(Self::UNKNOWN_LAG))
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+struct HopCountCost;
+
+impl LinkCostModel for HopCountCost {
+    fn link_cost(&self, _links: &[Link]) -> f64 {
+        1.0
+    }
+}
+
+struct DropAverseCost;
+
+impl DropAverseCost {
+    /// Extra cost per packet dropped in a link state slot on average, in hops
+    const DROP_PENALTY: f64 = 100.0;
+}
+
+impl LinkCostModel for DropAverseCost {
+    fn link_cost(&self, links: &[Link]) -> f64 {
+        links
+            .iter()
+            .map(|link| {
+                let avg_drops = average(&link.recent_link_states(), |s| s.drops as f64).unwrap_or(0.0);
+                1.0 + avg_drops * Self::DROP_PENALTY
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+fn average(samples: &[LinkStateEntry], f: impl Fn(&LinkStateEntry) -> f64) -> Option<f64> {
+    if samples.is_empty() {
+        None
+    } else {
+        Some(samples.iter().map(f).sum::<f64>() / samples.len() as f64)
+    }
+}
+
+#[test]
+fn test_cost_models() {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+
+    use cjdns_core::RoutingLabel;
+    use parking_lot::Mutex;
+
+    use crate::server::link::LinkStateMut;
+
+    let mk_link = |value: f64, samples: &[(u16, u16)]| Link {
+        label: RoutingLabel::try_new(0x13).expect("bad test label"),
+        encoding_form_number: 0,
+        peer_num: 0,
+        link_state: Arc::new(Mutex::new(
+            samples
+                .iter()
+                .enumerate()
+                .map(|(slot, &(drops, lag))| (slot as u64, LinkStateEntry { drops, lag, kb_recv: 100 }))
+                .collect::<HashMap<_, _>>(),
+        )),
+        value_history: Arc::new(Mutex::new(BTreeMap::new())),
+        create_time: 0,
+        mut_state: Arc::new(Mutex::new(LinkStateMut {
+            most_recent_ls_slot: 0,
+            mtu: 0,
+            flags: 0,
+            time: 0,
+            value,
+        })),
+    };
+
+    let good = mk_link(4.0, &[(0, 10), (0, 30)]);
+    let lossy = mk_link(2.0, &[(1, 5), (0, 5)]);
+    let unknown = mk_link(0.0, &[]);
+
+    let cost = |model: CostModel, links: &[Link]| model.model().link_cost(links);
+
+    assert_eq!(cost(CostModel::Value, std::slice::from_ref(&good)), 0.25);
+    assert_eq!(cost(CostModel::Value, &[lossy.clone(), good.clone()]), 0.25);
+    assert!(cost(CostModel::Value, std::slice::from_ref(&unknown)) > 1e19);
+
+    assert_eq!(cost(CostModel::Latency, std::slice::from_ref(&good)), 20.0);
+    assert_eq!(cost(CostModel::Latency, &[good.clone(), lossy.clone()]), 5.0);
+    assert_eq!(cost(CostModel::Latency, std::slice::from_ref(&unknown)), 1000.0);
+
+    assert_eq!(cost(CostModel::HopCount, std::slice::from_ref(&lossy)), 1.0);
+
+    assert_eq!(cost(CostModel::DropAverse, std::slice::from_ref(&good)), 1.0);
+    assert_eq!(cost(CostModel::DropAverse, std::slice::from_ref(&lossy)), 51.0);
+    assert_eq!(cost(CostModel::DropAverse, &[lossy, unknown]), 1.0);
+
+    assert_eq!("dropAverse".parse::<CostModel>(), Ok(CostModel::DropAverse));
+    assert!("foo".parse::<CostModel>().is_err());
+}
@@ -0,0 +1,174 @@
+//! Per-node debug tracing
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use cjdns_keys::CJDNS_IP6;
+
+/// Nodes being debugged, each with its own trace buffer.
+pub(super) struct DebugTrace {
+    traces: Mutex<HashMap<CJDNS_IP6, NodeTrace>>,
+}
+
+struct NodeTrace {
+    /// Lines are collected until this moment, the buffer is kept afterwards until cleared
+    expires_at: Instant,
+    /// Ring buffer, the oldest line first
+    lines: VecDeque<TraceLine>,
+    /// Number of lines pushed out of the buffer by the newer ones
+    dropped: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub(super) struct TraceLine {
+    /// Cjdns timestamp (milliseconds since the UNIX Epoch)
+    pub(super) time: u64,
+    pub(super) text: String,
+}
+
+/// State of a node trace.
+pub(super) struct TraceStatus {
+    pub(super) node: CJDNS_IP6,
+    /// Time left until the trace expires, `None` if it already has
+    pub(super) expires_in: Option<Duration>,
+    pub(super) lines: usize,
+    pub(super) dropped: u64,
+}
+
+impl DebugTrace {
+    /// Max number of lines kept per node.
+    pub(super) const BUFFER_LINES: usize = 1000;
+
+    pub(super) fn new() -> Self {
+        DebugTrace {
+            traces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start tracing the node until the given moment, or extend/shorten its existing trace.
+    /// The lines already collected are kept.
+    pub(super) fn start(&self, ip: CJDNS_IP6, until: Instant) {
+        let mut traces = self.traces.lock();
+        let trace = traces.entry(ip).or_insert_with(|| NodeTrace {
+            expires_at: until,
+            lines: VecDeque::new(),
+            dropped: 0,
+        });
+        trace.expires_at = until;
+    }
+
+    /// Stop tracing the node and drop its buffer. Returns `false` if the node wasn't traced.
+    pub(super) fn stop(&self, ip: &CJDNS_IP6) -> bool {
+        self.traces.lock().remove(ip).is_some()
+    }
+
+    /// Whether the node is traced and its trace hasn't expired yet.
+    pub(super) fn is_traced(&self, ip: &CJDNS_IP6, now: Instant) -> bool {
+        self.traces.lock().get(ip).is_some_and(|trace| now < trace.expires_at)
+    }
+
+    /// Append a line to the node's trace, if it is active, dropping the oldest line when the buffer is full.
+    pub(super) fn add(&self, ip: &CJDNS_IP6, now: Instant, time: u64, text: String) {
+        let mut traces = self.traces.lock();
+        let trace = match traces.get_mut(ip) {
+            Some(trace) if now < trace.expires_at => trace,
+            _ => return,
+        };
+        if trace.lines.len() >= Self::BUFFER_LINES {
+            trace.lines.pop_front();
+            trace.dropped += 1;
+        }
+        trace.lines.push_back(TraceLine { time, text });
+    }
+
+    /// Lines collected for the node, the oldest first. `None` if the node isn't traced.
+    pub(super) fn lines(&self, ip: &CJDNS_IP6) -> Option<Vec<TraceLine>> {
+        self.traces.lock().get(ip).map(|trace| trace.lines.iter().cloned().collect())
+    }
+
+    /// Empty the node's buffer. An expired trace is removed altogether.
+    /// Returns `false` if the node isn't traced.
+    pub(super) fn clear(&self, ip: &CJDNS_IP6, now: Instant) -> bool {
+        let mut traces = self.traces.lock();
+        let expired = match traces.get_mut(ip) {
+            Some(trace) => {
+                trace.lines.clear();
+                trace.dropped = 0;
+                trace.expires_at <= now
+            }
+            None => return false,
+        };
+        if expired {
+            traces.remove(ip);
+        }
+        true
+    }
+
+    /// All the traces, sorted by node IP.
+    pub(super) fn list(&self, now: Instant) -> Vec<TraceStatus> {
+        let traces = self.traces.lock();
+        let mut res = traces
+            .iter()
+            .map(|(ip, trace)| TraceStatus {
+                node: ip.clone(),
+                expires_in: trace.expires_at.checked_duration_since(now).filter(|d| *d > Duration::from_secs(0)),
+                lines: trace.lines.len(),
+                dropped: trace.dropped,
+            })
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.node.cmp(&b.node));
+        res
+    }
+}
+
+#[test]
+fn test_debug_trace() {
+    use std::convert::TryFrom;
+
+    let ip = |s: &str| CJDNS_IP6::try_from(s).expect("bad test ip");
+    let a = ip("fc00:0000:0000:0000:0000:0000:0000:0001");
+    let b = ip("fc00:0000:0000:0000:0000:0000:0000:0002");
+
+    let trace = DebugTrace::new();
+    let t0 = Instant::now();
+    let hour = Duration::from_secs(3600);
+    trace.start(a.clone(), t0 + hour);
+    trace.start(b.clone(), t0 + 2 * hour);
+
+    assert!(trace.is_traced(&a, t0));
+    trace.add(&a, t0, 1, "first".to_string());
+    trace.add(&b, t0, 1, "other".to_string());
+    assert_eq!(trace.lines(&a).unwrap().iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), vec!["first"]);
+
+    // Ring buffer keeps the newest lines
+    for i in 0..DebugTrace::BUFFER_LINES {
+        trace.add(&a, t0, 2, format!("line {}", i));
+    }
+    let lines = trace.lines(&a).unwrap();
+    assert_eq!(lines.len(), DebugTrace::BUFFER_LINES);
+    assert_eq!(lines[0].text, "line 0");
+    assert_eq!(trace.list(t0)[0].dropped, 1);
+
+    // Expired trace collects nothing, but its buffer can still be read
+    let t1 = t0 + hour;
+    assert!(!trace.is_traced(&a, t1));
+    assert!(trace.is_traced(&b, t1));
+    trace.add(&a, t1, 3, "late".to_string());
+    assert_eq!(trace.lines(&a).unwrap().len(), DebugTrace::BUFFER_LINES);
+    let status = trace.list(t1);
+    assert_eq!(status[0].expires_in, None);
+    assert_eq!(status[1].expires_in, Some(hour));
+
+    // Clearing empties an active buffer and removes an expired trace
+    assert!(trace.clear(&b, t1));
+    assert_eq!(trace.lines(&b).unwrap().len(), 0);
+    assert!(trace.clear(&a, t1));
+    assert!(trace.lines(&a).is_none());
+    assert!(!trace.clear(&a, t1));
+
+    assert!(trace.stop(&b));
+    assert!(!trace.is_traced(&b, t0));
+}
@@ -0,0 +1,117 @@
+//! Topology change events
+
+use serde::{Serialize, Serializer};
+use tokio::sync::broadcast;
+
+use cjdns_core::RoutingLabel;
+use cjdns_keys::CJDNS_IP6;
+
+use crate::utils::ip6_prefix::Ip6Prefix;
+
+/// Topology change event, sent to the subscribers of the event feed.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub(super) enum TopologyEvent {
+    #[serde(rename = "nodeAdded")]
+    NodeAdded {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+        version: u16,
+        key: String,
+    },
+
+    #[serde(rename = "nodeReset")]
+    NodeReset {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+        version: u16,
+        key: String,
+    },
+
+    #[serde(rename = "nodeForgotten")]
+    NodeForgotten {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+    },
+
+    #[serde(rename = "linkAdded")]
+    LinkAdded {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+        #[serde(serialize_with = "ser_ip6")]
+        peer: CJDNS_IP6,
+        #[serde(rename = "peerNum")]
+        peer_num: u16,
+        #[serde(serialize_with = "ser_label")]
+        label: RoutingLabel<u32>,
+    },
+
+    #[serde(rename = "linkWithdrawn")]
+    LinkWithdrawn {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+        #[serde(serialize_with = "ser_ip6")]
+        peer: CJDNS_IP6,
+        #[serde(rename = "peerNum")]
+        peer_num: u16,
+    },
+
+    #[serde(rename = "linkStateUpdated")]
+    LinkStateUpdated {
+        #[serde(serialize_with = "ser_ip6")]
+        node: CJDNS_IP6,
+        #[serde(serialize_with = "ser_ip6")]
+        peer: CJDNS_IP6,
+        #[serde(rename = "peerNum")]
+        peer_num: u16,
+        value: f64,
+    },
+}
+
+impl TopologyEvent {
+    /// Check whether the node (or, for link events, either end of the link) matches any of the prefixes.
+    /// Empty prefix list matches everything.
+    pub(super) fn matches(&self, prefixes: &[Ip6Prefix]) -> bool {
+        if prefixes.is_empty() {
+            return true;
+        }
+        use TopologyEvent::*;
+        let (node, peer) = match self {
+            NodeAdded { node, .. } | NodeReset { node, .. } | NodeForgotten { node } => (node, None),
+            LinkAdded { node, peer, .. } | LinkWithdrawn { node, peer, .. } | LinkStateUpdated { node, peer, .. } => (node, Some(peer)),
+        };
+        prefixes.iter().any(|p| p.matches(node) || peer.map(|peer| p.matches(peer)).unwrap_or(false))
+    }
+}
+
+fn ser_ip6<S: Serializer>(ip6: &CJDNS_IP6, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&ip6.to_string())
+}
+
+fn ser_label<S: Serializer>(label: &RoutingLabel<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    let label = RoutingLabel::<u64>::try_new(label.bits() as u64).expect("internal error: zero label");
+    serializer.serialize_str(&label.to_string())
+}
+
+/// Topology event feed.
+pub(super) struct Events {
+    tx: broadcast::Sender<TopologyEvent>,
+}
+
+impl Events {
+    pub(super) fn new() -> Self {
+        const QUEUE_SIZE: usize = 1024;
+        let (tx, _) = broadcast::channel(QUEUE_SIZE);
+        Events { tx }
+    }
+
+    /// Send event to all subscribers, if any.
+    pub(super) fn emit(&self, event: TopologyEvent) {
+        // Error only means there are no subscribers at the moment
+        let _ = self.tx.send(event);
+    }
+
+    pub(super) fn subscribe(&self) -> broadcast::Receiver<TopologyEvent> {
+        self.tx.subscribe()
+    }
+}
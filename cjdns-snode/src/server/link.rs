@@ -1,6 +1,6 @@
 //! Link state.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use parking_lot::Mutex;
@@ -8,12 +8,16 @@ use parking_lot::Mutex;
 use cjdns_ann::{Announcement, PeerData};
 use cjdns_core::RoutingLabel;
 
+use super::AGREED_TIMEOUT;
+
 #[derive(Clone)]
 pub(super) struct Link {
     pub(super) label: RoutingLabel<u32>,
     pub(super) encoding_form_number: u8,
     pub(super) peer_num: u16,
     pub(super) link_state: Arc<Mutex<HashMap<u64, LinkStateEntry>>>,
+    /// Decayed link value after each link state update, by timeslot of the update
+    pub(super) value_history: Arc<Mutex<BTreeMap<u64, f64>>>,
     pub(super) create_time: u64,
     pub(super) mut_state: Arc<Mutex<LinkStateMut>>,
 }
@@ -41,6 +45,7 @@ pub(super) fn mk_link(ann_peer: &PeerData, ann: &Announcement) -> Link {
         encoding_form_number: ann_peer.encoding_form_number,
         peer_num: ann_peer.peer_num,
         link_state: Arc::new(Mutex::new(HashMap::new())),
+        value_history: Arc::new(Mutex::new(BTreeMap::new())),
         create_time: ann_time,
         mut_state: Arc::new(Mutex::new(LinkStateMut {
             most_recent_ls_slot: ann_time / 1000 / 10,
@@ -52,9 +57,87 @@ pub(super) fn mk_link(ann_peer: &PeerData, ann: &Announcement) -> Link {
     }
 }
 
+/// Link state averaged over a range of timeslots.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct LinkHistoryPoint {
+    /// First timeslot of the range
+    pub(super) slot: u64,
+    /// Number of link state samples in the range
+    pub(super) samples: usize,
+    pub(super) drops: Option<f64>,
+    pub(super) lag: Option<f64>,
+    pub(super) kb_recv: Option<f64>,
+    /// Decayed link value
+    pub(super) value: Option<f64>,
+}
+
 impl Link {
     /// Each timeslot is 10 seconds, link state value halves every 3 minutes.
     pub(super) const DECAY_PER_TIMESLOT: f64 = 1.0 / 18.0;
+
+    /// Duration of a link state timeslot, seconds.
+    pub(super) const TIMESLOT_SECONDS: u64 = 10;
+
+    /// Link state samples reflecting the current link state, i.e. not older than the agreed timeout
+    /// before the most recent sample. Older samples are only kept as history.
+    pub(super) fn recent_link_states(&self) -> Vec<LinkStateEntry> {
+        let link_state = self.link_state.lock();
+        let latest = match link_state.keys().max() {
+            Some(&latest) => latest,
+            None => return Vec::new(),
+        };
+        let earliest = latest.saturating_sub(AGREED_TIMEOUT.as_secs() / Self::TIMESLOT_SECONDS);
+        link_state.iter().filter(|(&slot, _)| slot >= earliest).map(|(_, state)| state.clone()).collect()
+    }
+
+    /// Drop link state samples and link values recorded before the given timeslot.
+    pub(super) fn expire_history(&self, earliest_slot: u64) {
+        self.link_state.lock().retain(|&slot, _| slot >= earliest_slot);
+        let mut value_history = self.value_history.lock();
+        *value_history = value_history.split_off(&earliest_slot);
+    }
+
+    /// Link state history within the timeslot range (inclusive), averaged over `step` timeslots.
+    /// Ranges are aligned to multiples of `step`, ranges without data are omitted.
+    pub(super) fn history(&self, first_slot: u64, last_slot: u64, step: u64) -> Vec<LinkHistoryPoint> {
+        #[derive(Default)]
+        struct Sums {
+            samples: usize,
+            drops: f64,
+            lag: f64,
+            kb_recv: f64,
+            values: usize,
+            value: f64,
+        }
+
+        let step = step.max(1);
+        let in_range = |slot: u64| slot >= first_slot && slot <= last_slot;
+        let mut sums = BTreeMap::<u64, Sums>::new();
+        for (&slot, state) in self.link_state.lock().iter().filter(|(&slot, _)| in_range(slot)) {
+            let s = sums.entry(slot - slot % step).or_default();
+            s.samples += 1;
+            s.drops += state.drops as f64;
+            s.lag += state.lag as f64;
+            s.kb_recv += state.kb_recv as f64;
+        }
+        for (&slot, &value) in self.value_history.lock().range(first_slot..=last_slot) {
+            let s = sums.entry(slot - slot % step).or_default();
+            s.values += 1;
+            s.value += value;
+        }
+
+        let avg = |sum: f64, count: usize| if count > 0 { Some(sum / count as f64) } else { None };
+        sums.into_iter()
+            .map(|(slot, s)| LinkHistoryPoint {
+                slot,
+                samples: s.samples,
+                drops: avg(s.drops, s.samples),
+                lag: avg(s.lag, s.samples),
+                kb_recv: avg(s.kb_recv, s.samples),
+                value: avg(s.value, s.values),
+            })
+            .collect()
+    }
 }
 
 impl LinkStateEntry {
@@ -75,3 +158,56 @@ impl LinkStateEntry {
         kb_recv / (lag * f64::powi(2.0, drops))
     }
 }
+
+#[test]
+fn test_link_history() {
+    let link = Link {
+        label: RoutingLabel::try_new(0x13).expect("bad test label"),
+        encoding_form_number: 0,
+        peer_num: 0,
+        link_state: Arc::new(Mutex::new(HashMap::new())),
+        value_history: Arc::new(Mutex::new(BTreeMap::new())),
+        create_time: 0,
+        mut_state: Arc::new(Mutex::new(LinkStateMut {
+            most_recent_ls_slot: 0,
+            mtu: 0,
+            flags: 0,
+            time: 0,
+            value: 0.0,
+        })),
+    };
+    {
+        let mut link_state = link.link_state.lock();
+        link_state.insert(1000, LinkStateEntry { drops: 0, lag: 10, kb_recv: 100 });
+        link_state.insert(1001, LinkStateEntry { drops: 2, lag: 30, kb_recv: 300 });
+        link_state.insert(1005, LinkStateEntry { drops: 1, lag: 20, kb_recv: 200 });
+        link_state.insert(1200, LinkStateEntry { drops: 0, lag: 40, kb_recv: 400 });
+        let mut value_history = link.value_history.lock();
+        value_history.insert(1001, 4.0);
+        value_history.insert(1003, 2.0);
+        value_history.insert(1200, 1.0);
+    }
+
+    let history = link.history(1000, 1010, 1);
+    assert_eq!(history.iter().map(|p| p.slot).collect::<Vec<_>>(), vec![1000, 1001, 1003, 1005]);
+    assert_eq!(history[1].drops, Some(2.0));
+    assert_eq!(history[1].value, Some(4.0));
+    assert_eq!(history[2].samples, 0);
+    assert_eq!(history[2].lag, None);
+
+    let downsampled = link.history(0, u64::MAX, 4);
+    assert_eq!(
+        downsampled,
+        vec![
+            LinkHistoryPoint { slot: 1000, samples: 2, drops: Some(1.0), lag: Some(20.0), kb_recv: Some(200.0), value: Some(3.0) },
+            LinkHistoryPoint { slot: 1004, samples: 1, drops: Some(1.0), lag: Some(20.0), kb_recv: Some(200.0), value: None },
+            LinkHistoryPoint { slot: 1200, samples: 1, drops: Some(0.0), lag: Some(40.0), kb_recv: Some(400.0), value: Some(1.0) },
+        ]
+    );
+
+    // Only the samples within the agreed timeout before the latest one are current
+    assert_eq!(link.recent_link_states().len(), 1);
+
+    link.expire_history(1003);
+    assert_eq!(link.history(0, u64::MAX, 1).iter().map(|p| p.slot).collect::<Vec<_>>(), vec![1003, 1005, 1200]);
+}
@@ -0,0 +1,111 @@
+//! Metrics in Prometheus text exposition format
+
+use std::fmt::Write;
+
+use crate::server::Server;
+
+/// Render server metrics in Prometheus text format.
+pub(super) fn render(server: &Server) -> String {
+    let mut out = Metrics(String::new());
+
+    out.header("snode_announcements_processed_total", "Announcements processed, by reply error", "counter");
+    for (reply_err, count) in server.stats.anns_processed() {
+        out.sample("snode_announcements_processed_total", &[("error", &reply_err)], count as f64);
+    }
+
+    out.header("snode_nodes", "Number of known nodes", "gauge");
+    out.sample("snode_nodes", &[], server.nodes.count() as f64);
+
+    out.header("snode_links", "Number of known links", "gauge");
+    out.sample("snode_links", &[], server.nodes.link_count() as f64);
+
+    let asymmetric_links = server.asymmetric_links.update(&server.nodes, server.clock.instant());
+    out.header("snode_asymmetric_links", "Number of links announced in one direction only", "gauge");
+    out.sample("snode_asymmetric_links", &[], asymmetric_links.len() as f64);
+    let max_age = asymmetric_links.first().map_or(0.0, |oldest| oldest.duration.as_secs_f64());
+    out.header("snode_asymmetric_link_max_age_seconds", "How long the longest lasting asymmetric link has been so", "gauge");
+    out.sample("snode_asymmetric_link_max_age_seconds", &[], max_age);
+
+    let routing_stats = server.routing.stats();
+    out.header("snode_route_cache_hits_total", "Route cache hits", "counter");
+    out.sample("snode_route_cache_hits_total", &[], routing_stats.cache_hits as f64);
+    out.header("snode_route_cache_misses_total", "Route cache misses", "counter");
+    out.sample("snode_route_cache_misses_total", &[], routing_stats.cache_misses as f64);
+    out.header("snode_route_graph_rebuilds_total", "Routing graph rebuilds", "counter");
+    out.sample("snode_route_graph_rebuilds_total", &[], routing_stats.rebuilds as f64);
+    out.header("snode_route_graph_updates_total", "Incremental routing graph updates", "counter");
+    out.sample("snode_route_graph_updates_total", &[], routing_stats.updates as f64);
+    if let Some(duration) = routing_stats.last_rebuild_duration {
+        out.header("snode_route_graph_rebuild_seconds", "Duration of the last routing graph rebuild", "gauge");
+        out.sample("snode_route_graph_rebuild_seconds", &[], duration.as_secs_f64());
+    }
+
+    let peers_info = server.peers.get_info();
+    out.header("snode_peer_outstanding_requests", "Outstanding requests to a peer supernode", "gauge");
+    for pi in peers_info.peers.iter() {
+        out.sample("snode_peer_outstanding_requests", &[("addr", &pi.addr)], pi.outstanding_requests as f64);
+    }
+    out.header("snode_peer_last_message_age_seconds", "Time since the last message from a peer supernode", "gauge");
+    for pi in peers_info.peers.iter() {
+        out.sample("snode_peer_last_message_age_seconds", &[("addr", &pi.addr)], pi.last_msg_age.as_secs_f64());
+    }
+
+    if let Some(age) = server.stats.last_sniffer_msg_age(server.clock.instant()) {
+        out.header("snode_sniffer_last_message_age_seconds", "Time since the last message from the local cjdns router", "gauge");
+        out.sample("snode_sniffer_last_message_age_seconds", &[], age.as_secs_f64());
+    }
+
+    out.0
+}
+
+struct Metrics(String);
+
+impl Metrics {
+    fn header(&mut self, name: &str, help: &str, metric_type: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.0, "{}", name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.0, "{{{}}}", labels);
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[test]
+fn test_metrics_format() {
+    let mut m = Metrics(String::new());
+    m.header("foo_total", "Foo count", "counter");
+    m.sample("foo_total", &[], 1.0);
+    m.sample("foo_total", &[("addr", "a\"b"), ("x", "y")], 2.5);
+    assert_eq!(
+        m.0,
+        "# HELP foo_total Foo count\n# TYPE foo_total counter\nfoo_total 1\nfoo_total{addr=\"a\\\"b\",x=\"y\"} 2.5\n"
+    );
+}
+
+#[test]
+fn test_render_without_asymmetric_links() {
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use crate::server::tests::test_server;
+    use crate::utils::clock::ManualClock;
+
+    let server = test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH)));
+    let metrics = render(&server);
+    assert!(metrics.contains("\nsnode_asymmetric_links 0\n"));
+    assert!(metrics.contains("\nsnode_asymmetric_link_max_age_seconds 0\n"));
+}
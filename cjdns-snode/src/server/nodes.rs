@@ -72,11 +72,34 @@ impl Nodes {
         self.nodes_by_ip.read().len()
     }
 
+    pub fn link_count(&self) -> usize {
+        let nodes_by_ip = self.nodes_by_ip.read();
+        nodes_by_ip.values().map(|node| node.inward_links_by_ip.lock().values().map(Vec::len).sum::<usize>()).sum()
+    }
+
     pub fn anns_dump(&self) -> Vec<u8> {
+        self.write_anns(false)
+    }
+
+    /// Same as `anns_dump()`, but also includes reset messages which are no longer
+    /// in the list of announcements, so the node table can be restored from it.
+    pub fn anns_snapshot(&self) -> Vec<u8> {
+        self.write_anns(true)
+    }
+
+    fn write_anns(&self, with_reset_msgs: bool) -> Vec<u8> {
         let mut writer = Writer::new();
         let nodes_by_ip = self.nodes_by_ip.read();
         for node in nodes_by_ip.values() {
             let state = node.mut_state.read();
+            if with_reset_msgs {
+                if let Some(reset_msg) = state.reset_msg.as_ref() {
+                    if !state.announcements.contains(reset_msg) {
+                        writer.write_u32_be(reset_msg.binary.len() as u32);
+                        writer.write_slice(&reset_msg.binary);
+                    }
+                }
+            }
             for ann in &state.announcements {
                 writer.write_u32_be(ann.binary.len() as u32);
                 writer.write_slice(&ann.binary);
@@ -86,22 +109,27 @@ impl Nodes {
         writer.into_vec()
     }
 
-    pub fn keep_table_clean(&self) {
+    /// Forget nodes which haven't announced for too long as of `now`. Returns the list of forgotten nodes.
+    pub fn keep_table_clean(&self, now: SystemTime) -> Vec<CJDNS_IP6> {
         trace!("keep_table_clean()");
 
-        let min_time = SystemTime::now() - super::GLOBAL_TIMEOUT;
+        let min_time = now - super::GLOBAL_TIMEOUT;
 
+        let mut forgotten = Vec::new();
         let mut nodes_by_ip = self.nodes_by_ip.write();
-        nodes_by_ip.retain(|_node_ip, node| {
+        nodes_by_ip.retain(|node_ip, node| {
             let node_mut = node.mut_state.read();
             if node_mut.timestamp < min_time {
                 warn!("forgetting node [{}]", node.ipv6);
                 self.forget_node(node.clone());
+                forgotten.push(node_ip.clone());
                 false // Remove node
             } else {
                 true // Keep this node
             }
         });
+
+        forgotten
     }
 
     pub(super) fn new_node(
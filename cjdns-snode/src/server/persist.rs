@@ -0,0 +1,120 @@
+//! Node table persistence
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Error;
+use tokio::fs;
+
+use cjdns_ann::AnnouncementPacket;
+use cjdns_bytes::Reader;
+use cjdns_keys::CJDNS_IP6;
+
+use crate::peer::AnnData;
+use crate::server::{ReplyError, Server, GLOBAL_TIMEOUT};
+use crate::utils::timestamp::mktime;
+
+/// Save all stored announcements to the specified file.
+/// The file is written in the same format as the `/dump` endpoint produces.
+pub(super) async fn save_state(server: &Server, file_path: &Path) -> Result<(), Error> {
+    let data = server.nodes.anns_snapshot();
+    // Write to a temporary file first, so the previous snapshot survives if we crash halfway
+    let tmp_path = file_path.with_extension("tmp");
+    fs::write(&tmp_path, data)
+        .await
+        .map_err(|e| anyhow!("failed to write state file '{}': {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, file_path)
+        .await
+        .map_err(|e| anyhow!("failed to replace state file '{}': {}", file_path.display(), e))?;
+    debug!("Saved state to '{}'", file_path.display());
+    Ok(())
+}
+
+/// Restore node table from the previously saved file, if any.
+pub(super) async fn load_state(server: &Server, file_path: &Path) -> Result<(), Error> {
+    let data = match fs::read(file_path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!("State file '{}' not found, starting with empty node table", file_path.display());
+            return Ok(());
+        }
+        Err(e) => return Err(anyhow!("failed to read state file '{}': {}", file_path.display(), e)),
+    };
+    let anns = parse_anns_dump(&data).map_err(|e| anyhow!("failed to parse state file '{}': {}", file_path.display(), e))?;
+    let (total, accepted) = server.replay_anns(anns).await;
+    info!("Restored {} of {} announcements from '{}'", accepted, total, file_path.display());
+    Ok(())
+}
+
+/// Split announcements dump (as produced by `Nodes::anns_dump()`) into separate announcements.
+pub(super) fn parse_anns_dump(data: &[u8]) -> Result<Vec<AnnData>, Error> {
+    let mut reader = Reader::new(data);
+    let mut anns = Vec::new();
+    loop {
+        let len = reader.read_u32_be().map_err(|_| anyhow!("unexpected end of data"))? as usize;
+        if len == 0 {
+            break;
+        }
+        let ann = reader.read_slice(len).map_err(|_| anyhow!("truncated announcement"))?;
+        anns.push(ann.to_vec());
+    }
+    Ok(anns)
+}
+
+/// Parse announcements and sort them oldest first, with reset messages before other announcements of the same time.
+/// Returns timestamp, node IP and the announcement itself, unparsable announcements are dropped.
+pub(super) fn sorted_for_replay(anns: Vec<AnnData>) -> Vec<(u64, CJDNS_IP6, AnnData)> {
+    let mut parsed = anns
+        .into_iter()
+        .filter_map(|data| {
+            let ann = AnnouncementPacket::try_new(data.clone()).ok()?.parse().ok()?;
+            Some((ann.header.timestamp, !ann.header.is_reset, ann.node_ip, data))
+        })
+        .collect::<Vec<_>>();
+    parsed.sort_by_key(|&(timestamp, not_reset, _, _)| (timestamp, not_reset));
+    parsed.into_iter().map(|(timestamp, _, node_ip, data)| (timestamp, node_ip, data)).collect()
+}
+
+impl Server {
+    /// Feed stored announcements through the regular announcement handling path.
+    /// Announcements are replayed oldest first, so reset messages come before the updates they precede.
+    /// Nodes which would be forgotten by `keep_table_clean()` anyway are skipped.
+    /// Returns total number of announcements and number of accepted ones.
+    pub(super) async fn replay_anns(&self, anns: Vec<AnnData>) -> (usize, usize) {
+        let total = anns.len();
+
+        let mut parsed = sorted_for_replay(anns);
+
+        let mut last_seen = HashMap::<CJDNS_IP6, u64>::new();
+        for (timestamp, node_ip, _) in parsed.iter() {
+            let ts = last_seen.entry(node_ip.clone()).or_insert(*timestamp);
+            if *ts < *timestamp {
+                *ts = *timestamp;
+            }
+        }
+
+        let min_time = self.clock.now() - GLOBAL_TIMEOUT;
+        parsed.retain(|(_, node_ip, _)| mktime(last_seen[node_ip]) >= min_time);
+
+        let mut accepted = 0;
+        for (_, _, data) in parsed {
+            match self.handle_announce_impl(data, false, None).await {
+                Ok((_, ReplyError::None)) => accepted += 1,
+                Ok((_, err)) => debug!("Stored announcement rejected: {}", err),
+                Err(err) => debug!("Bad stored announcement: {}", err),
+            }
+        }
+
+        (total, accepted)
+    }
+}
+
+#[test]
+fn test_parse_anns_dump() {
+    assert_eq!(parse_anns_dump(&[0, 0, 0, 0]).unwrap(), Vec::<AnnData>::new());
+    assert_eq!(parse_anns_dump(&[0, 0, 0, 2, 1, 2, 0, 0, 0, 1, 3, 0, 0, 0, 0]).unwrap(), vec![vec![1, 2], vec![3]]);
+    assert!(parse_anns_dump(&[]).is_err());
+    assert!(parse_anns_dump(&[0, 0, 0, 2, 1]).is_err());
+    assert!(parse_anns_dump(&[0, 0, 0, 1, 1]).is_err());
+}
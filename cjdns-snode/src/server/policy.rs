@@ -0,0 +1,106 @@
+//! Node allow/deny policy for accepted announcements
+
+use cjdns_keys::{CJDNSPublicKey, CJDNS_IP6};
+
+use crate::config::AnnPolicyConfig;
+
+impl AnnPolicyConfig {
+    /// Check whether announcements from the node with the given key, address and protocol version are accepted.
+    pub(super) fn accepts(&self, key: &CJDNSPublicKey, ip: &CJDNS_IP6, version: Option<u16>) -> bool {
+        let key = key.to_string();
+
+        if self.deny_keys.contains(&key) || self.deny_prefixes.iter().any(|p| p.matches(ip)) {
+            return false;
+        }
+
+        if !self.allow_keys.is_empty() || !self.allow_prefixes.is_empty() {
+            let allowed = self.allow_keys.contains(&key) || self.allow_prefixes.iter().any(|p| p.matches(ip));
+            if !allowed {
+                return false;
+            }
+        }
+
+        match (self.min_version, version) {
+            (Some(min_version), Some(version)) => version >= min_version,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Check whether links to the node with the given address are accepted.
+    /// `known` is the key and version of the node if it is in the node table. For a node we haven't heard from yet
+    /// only the prefix rules can be checked; the rest apply once it announces itself.
+    pub(super) fn accepts_peer(&self, ip: &CJDNS_IP6, known: Option<(&CJDNSPublicKey, u16)>) -> bool {
+        if let Some((key, version)) = known {
+            return self.accepts(key, ip, Some(version));
+        }
+
+        if self.deny_prefixes.iter().any(|p| p.matches(ip)) {
+            return false;
+        }
+
+        // A node is only known to be allowed by a key once it has announced itself
+        self.allow_prefixes.iter().any(|p| p.matches(ip)) || (self.allow_prefixes.is_empty() && self.allow_keys.is_empty())
+    }
+}
+
+#[test]
+fn test_policy() {
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    let key = CJDNSPublicKey::try_from("xpr2z2s3hnr0qzpk2u121uqjv15dc335v54pccqlqj6c5p840yy0.k").expect("bad test key");
+    let ip = CJDNS_IP6::try_from(&key).expect("bad test key");
+    let other_key = CJDNSPublicKey::try_from("qgkjd0stfvk9r3j28s4gh8rgslbgx2r5xgxzxkgm5vdxqwn8xsu0.k").expect("bad test key");
+    let other_ip = CJDNS_IP6::try_from(&other_key).expect("bad test key");
+    let prefix = |s: &str| FromStr::from_str(s).expect("bad test prefix");
+
+    let policy = AnnPolicyConfig::default();
+    assert!(policy.accepts(&key, &ip, None));
+
+    let policy = AnnPolicyConfig {
+        deny_keys: vec![key.to_string()],
+        ..AnnPolicyConfig::default()
+    };
+    assert!(!policy.accepts(&key, &ip, Some(21)));
+    assert!(policy.accepts(&other_key, &other_ip, Some(21)));
+
+    let policy = AnnPolicyConfig {
+        allow_prefixes: vec![prefix(&format!("{}/64", ip))],
+        ..AnnPolicyConfig::default()
+    };
+    assert!(policy.accepts(&key, &ip, Some(21)));
+    assert!(!policy.accepts(&other_key, &other_ip, Some(21)));
+
+    let policy = AnnPolicyConfig {
+        allow_keys: vec![other_key.to_string()],
+        deny_prefixes: vec![prefix(&other_ip.to_string())],
+        ..AnnPolicyConfig::default()
+    };
+    assert!(!policy.accepts(&key, &ip, Some(21)), "not in allow list");
+    assert!(!policy.accepts(&other_key, &other_ip, Some(21)), "deny takes precedence");
+
+    let policy = AnnPolicyConfig {
+        min_version: Some(20),
+        ..AnnPolicyConfig::default()
+    };
+    assert!(policy.accepts(&key, &ip, Some(20)));
+    assert!(!policy.accepts(&key, &ip, Some(19)));
+    assert!(!policy.accepts(&key, &ip, None));
+
+    // Links to nodes not in the node table are checked by address only
+    let policy = AnnPolicyConfig {
+        deny_prefixes: vec![prefix(&format!("{}/64", other_ip))],
+        min_version: Some(20),
+        ..AnnPolicyConfig::default()
+    };
+    assert!(policy.accepts_peer(&ip, None));
+    assert!(!policy.accepts_peer(&other_ip, None));
+    assert!(!policy.accepts_peer(&ip, Some((&key, 19))));
+    let policy = AnnPolicyConfig {
+        allow_keys: vec![key.to_string()],
+        ..AnnPolicyConfig::default()
+    };
+    assert!(!policy.accepts_peer(&ip, None), "allowed key not known yet");
+    assert!(policy.accepts_peer(&ip, Some((&key, 21))));
+}
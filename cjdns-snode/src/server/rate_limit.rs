@@ -0,0 +1,127 @@
+//! Announcement rate limiting
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+use cjdns_keys::CJDNS_IP6;
+
+use crate::config::{RateBucketConfig, RateLimitConfig};
+
+/// Token bucket based rate limiter for the announcements received from the local node.
+/// Applies both per announcing node limit and global limit.
+pub(super) struct RateLimiter {
+    config: RateLimitConfig,
+    per_node: Mutex<HashMap<CJDNS_IP6, TokenBucket>>,
+    global: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(super) fn new(config: RateLimitConfig, now: Instant) -> Self {
+        let global = TokenBucket::new(config.global.as_ref(), now);
+        RateLimiter {
+            config,
+            per_node: Mutex::new(HashMap::new()),
+            global: Mutex::new(global),
+        }
+    }
+
+    /// Try to accept one announcement from the given node.
+    /// Returns `false` if the announcement should be throttled.
+    pub(super) fn try_accept(&self, node_ip: &CJDNS_IP6, now: Instant) -> bool {
+        if let Some(bucket_config) = self.config.per_node.as_ref() {
+            let mut per_node = self.per_node.lock();
+            let bucket = per_node.entry(node_ip.clone()).or_insert_with(|| TokenBucket::new(Some(bucket_config), now));
+            if !bucket.try_take(bucket_config, now) {
+                return false;
+            }
+        }
+        if let Some(bucket_config) = self.config.global.as_ref() {
+            if !self.global.lock().try_take(bucket_config, now) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Forget per-node buckets which are full again, so the map doesn't grow indefinitely.
+    pub(super) fn cleanup(&self, now: Instant) {
+        if let Some(bucket_config) = self.config.per_node.as_ref() {
+            self.per_node.lock().retain(|_, bucket| !bucket.is_full(bucket_config, now));
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: Option<&RateBucketConfig>, now: Instant) -> Self {
+        TokenBucket {
+            tokens: config.map(|c| c.burst as f64).unwrap_or(0.0),
+            last_update: now,
+        }
+    }
+
+    fn refill(&mut self, config: &RateBucketConfig, now: Instant) {
+        if now > self.last_update {
+            let elapsed = now.duration_since(self.last_update).as_secs_f64();
+            let added = elapsed * config.per_minute as f64 / 60.0;
+            self.tokens = (self.tokens + added).min(config.burst as f64);
+            self.last_update = now;
+        }
+    }
+
+    fn try_take(&mut self, config: &RateBucketConfig, now: Instant) -> bool {
+        self.refill(config, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&mut self, config: &RateBucketConfig, now: Instant) -> bool {
+        self.refill(config, now);
+        self.tokens >= config.burst as f64
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    let node1 = CJDNS_IP6::try_from("fc50:71b5:aebf:7b70:6577:0ec8:2542:9dd9").unwrap();
+    let node2 = CJDNS_IP6::try_from("fc00:0000:0000:0000:0000:0000:0000:0001").unwrap();
+    let t0 = Instant::now();
+    let limiter = RateLimiter::new(
+        RateLimitConfig {
+            per_node: Some(RateBucketConfig { per_minute: 60, burst: 2 }),
+            global: Some(RateBucketConfig { per_minute: 120, burst: 3 }),
+        },
+        t0,
+    );
+
+    assert!(limiter.try_accept(&node1, t0));
+    assert!(limiter.try_accept(&node1, t0));
+    assert!(!limiter.try_accept(&node1, t0), "per-node burst exceeded");
+    assert!(limiter.try_accept(&node2, t0));
+    assert!(!limiter.try_accept(&node2, t0), "global burst exceeded");
+
+    // One token per second for each node, two tokens per second globally
+    let t1 = t0 + Duration::from_secs(1);
+    assert!(limiter.try_accept(&node1, t1));
+    assert!(!limiter.try_accept(&node1, t1));
+    assert!(limiter.try_accept(&node2, t1));
+    assert!(!limiter.try_accept(&node2, t1));
+
+    // After a long pause all buckets are full again and can be forgotten
+    let t2 = t0 + Duration::from_secs(60);
+    limiter.cleanup(t2);
+    assert!(limiter.per_node.lock().is_empty());
+}
@@ -0,0 +1,71 @@
+//! Config reloading at runtime
+
+use anyhow::Error;
+
+use crate::config::{self, Config};
+use crate::server::Server;
+
+impl Server {
+    /// Re-read the config file and apply the changes.
+    /// Only the peer list, the admin token, the access tokens, the announcement policy, the max routes count,
+    /// the default cost model, the path solver, the link state retention and the minimum node version
+    /// can be changed at runtime,
+    /// other settings keep their current values until restart.
+    /// Returns the resulting effective config.
+    pub(super) async fn reload_config(&self) -> Result<Config, Error> {
+        let new_config = config::load(&self.config_file).await?;
+
+        let effective = {
+            let mut config = self.config.write();
+            if new_config.connect != config.connect
+                || new_config.state_file != config.state_file
+                || new_config.state_save_interval != config.state_save_interval
+                || new_config.listeners != config.listeners
+                || new_config.ann_rate_limit != config.ann_rate_limit
+            {
+                warn!("Some of the config changes require restart to take effect");
+            }
+            let effective = Config {
+                peers: new_config.peers,
+                admin_token: new_config.admin_token,
+                auth: new_config.auth,
+                ann_policy: new_config.ann_policy,
+                max_routes: new_config.max_routes,
+                cost_model: new_config.cost_model,
+                path_solver: new_config.path_solver,
+                link_state_retention: new_config.link_state_retention,
+                min_node_version: new_config.min_node_version,
+                ..config.clone()
+            };
+            *config = effective.clone();
+            effective
+        };
+
+        self.peers.set_peer_secret(effective.auth.as_ref().and_then(|auth| auth.peer_secret.clone()));
+        self.peers.set_outgoing_peers(&effective.peers);
+        info!("Config reloaded from '{}'", self.config_file.display());
+
+        Ok(effective)
+    }
+}
+
+/// Reload config each time the process receives SIGHUP.
+#[cfg(unix)]
+pub(super) async fn reload_on_sighup(server: std::sync::Arc<Server>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Unable to listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading config");
+        if let Err(err) = server.reload_config().await {
+            error!("Failed to reload config: {}", err);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Offline replay of recorded announcements
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::fs;
+
+use cjdns_crypto::hash::sha512;
+use cjdns_keys::CJDNS_IP6;
+
+use crate::config::Config;
+use crate::peer::create_peers;
+use crate::server::persist::{parse_anns_dump, sorted_for_replay};
+use crate::server::route::{get_routes, RouteConstraints};
+use crate::server::{ReplyError, Server, KEEP_TABLE_CLEAN_CYCLE};
+use crate::utils::clock::{Clock, ManualClock};
+use crate::utils::timestamp::{make_timestamp, mktime};
+
+/// Replay options.
+pub struct ReplayOptions {
+    /// Number of node pairs to find routes between
+    pub sample_routes: usize,
+    /// Write the report as JSON instead of text
+    pub json: bool,
+    /// File to write the report to (stdout if not set)
+    pub output: Option<PathBuf>,
+}
+
+/// Resulting state of the replay.
+#[derive(Serialize)]
+struct ReplayReport {
+    /// Announcements in the dump
+    total: usize,
+    /// Announcements accepted by the server
+    accepted: usize,
+    /// Simulated clock at the end of the replay, cjdns timestamp
+    clock: u64,
+    /// Hash over the state hashes of all the nodes, to compare replays at a glance
+    #[serde(rename = "tableHash")]
+    table_hash: String,
+    nodes: Vec<ReplayNode>,
+    routes: Vec<ReplayRoute>,
+}
+
+#[derive(Serialize)]
+struct ReplayNode {
+    ip: String,
+    version: u16,
+    key: String,
+    timestamp: u64,
+    announcements: usize,
+    links: usize,
+    #[serde(rename = "stateHash")]
+    state_hash: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReplayRoute {
+    src: String,
+    tar: String,
+    /// Label and the hops (the node IPs) of each alternative route, empty if there is no route
+    routes: Vec<(String, Vec<String>)>,
+}
+
+/// Run the announcements from the dump file through the announcement handling,
+/// with the clock following the announcement timestamps, and report the resulting node table.
+/// No connections are made, the only I/O is reading the dump and writing the report.
+pub async fn replay(config_file: PathBuf, config: Config, dump_file: &Path, options: ReplayOptions) -> Result<()> {
+    let data = fs::read(dump_file)
+        .await
+        .map_err(|e| anyhow!("failed to read dump file '{}': {}", dump_file.display(), e))?;
+    let anns = parse_anns_dump(&data).map_err(|e| anyhow!("failed to parse dump file '{}': {}", dump_file.display(), e))?;
+
+    let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+    let (peers, _announces) = create_peers(clock.clone());
+    let server = Arc::new(Server::new(Arc::new(peers), clock.clone(), config_file, config));
+    let report = server.replay_report(&clock, anns, options.sample_routes).await;
+
+    let out = if options.json {
+        serde_json::to_string_pretty(&report).expect("internal error: replay report isn't serializable")
+    } else {
+        report.to_text()
+    };
+    match options.output.as_ref() {
+        Some(path) => fs::write(path, out)
+            .await
+            .map_err(|e| anyhow!("failed to write report file '{}': {}", path.display(), e))?,
+        None => println!("{}", out),
+    }
+    Ok(())
+}
+
+impl Server {
+    async fn replay_report(self: &Arc<Self>, clock: &ManualClock, anns: Vec<Vec<u8>>, sample_routes: usize) -> ReplayReport {
+        let total = anns.len();
+        let accepted = self.replay_with_clock(clock, anns).await;
+
+        let mut ips = self.nodes.all_ips();
+        ips.sort();
+
+        let mut table_hash = sha512::State::new();
+        let mut nodes = Vec::with_capacity(ips.len());
+        for ip in ips.iter() {
+            let node = match self.nodes.by_ip(ip) {
+                Some(node) => node,
+                None => continue,
+            };
+            let node_mut = node.mut_state.read();
+            let state_hash = node_mut.state_hash.as_ref().map(|hash| hex::encode(hash.bytes()));
+            table_hash.update(&ip[..]);
+            table_hash.update(state_hash.as_deref().unwrap_or("").as_bytes());
+            nodes.push(ReplayNode {
+                ip: ip.to_string(),
+                version: node.version,
+                key: node.key.to_string(),
+                timestamp: make_timestamp(node_mut.timestamp),
+                announcements: node_mut.announcements.len(),
+                links: node.inward_links_by_ip.lock().values().map(Vec::len).sum(),
+                state_hash,
+            });
+        }
+
+        let routes = sample_pairs(&ips, sample_routes)
+            .into_iter()
+            .map(|(src, tar)| self.replay_route(src, tar))
+            .collect();
+
+        ReplayReport {
+            total,
+            accepted,
+            clock: make_timestamp(clock.now()),
+            table_hash: hex::encode(table_hash.finalize()),
+            nodes,
+            routes,
+        }
+    }
+
+    /// Feed the announcements oldest first, moving the server clock along with the announcement timestamps
+    /// and expiring nodes as the clock passes each cleanup cycle. Returns the number of accepted announcements.
+    async fn replay_with_clock(&self, clock: &ManualClock, anns: Vec<Vec<u8>>) -> usize {
+        let mut accepted = 0;
+        let mut next_cleanup = None;
+        for (timestamp, _, data) in sorted_for_replay(anns) {
+            clock.advance_to(mktime(timestamp));
+            let now = clock.now();
+            match next_cleanup {
+                Some(cleanup_time) if now < cleanup_time => {}
+                Some(_) => {
+                    self.forget_old_nodes();
+                    next_cleanup = Some(now + KEEP_TABLE_CLEAN_CYCLE);
+                }
+                None => next_cleanup = Some(now + KEEP_TABLE_CLEAN_CYCLE),
+            }
+            match self.handle_announce_impl(data, false, None).await {
+                Ok((_, ReplyError::None)) => accepted += 1,
+                Ok((_, err)) => debug!("Replayed announcement rejected: {}", err),
+                Err(err) => debug!("Bad replayed announcement: {}", err),
+            }
+        }
+        if next_cleanup.is_some() {
+            self.forget_old_nodes();
+        }
+        accepted
+    }
+
+    fn replay_route(self: &Arc<Self>, src: &CJDNS_IP6, tar: &CJDNS_IP6) -> ReplayRoute {
+        let (max_routes, cost_model) = {
+            let config = self.config.read();
+            (config.max_routes, config.cost_model)
+        };
+        let routes = get_routes(
+            self.clone(),
+            self.nodes.by_ip(src),
+            self.nodes.by_ip(tar),
+            max_routes,
+            &RouteConstraints::default(),
+            cost_model,
+        );
+        let routes = routes.unwrap_or_default().into_iter().map(|route| {
+            let hops = route.path.iter().map(|ip| ip.to_string()).collect();
+            (route.label.to_string(), hops)
+        });
+        ReplayRoute {
+            src: src.to_string(),
+            tar: tar.to_string(),
+            routes: routes.collect(),
+        }
+    }
+}
+
+impl ReplayReport {
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Replayed {} announcements, {} accepted, clock stopped at {}", self.total, self.accepted, self.clock).unwrap();
+        writeln!(out, "{} nodes, table hash {}", self.nodes.len(), self.table_hash).unwrap();
+        for node in self.nodes.iter() {
+            writeln!(
+                out,
+                "  {} v{} ts {} anns {} links {} hash {}",
+                node.ip,
+                node.version,
+                node.timestamp,
+                node.announcements,
+                node.links,
+                node.state_hash.as_deref().unwrap_or("-")
+            )
+            .unwrap();
+        }
+        writeln!(out, "{} sample routes", self.routes.len()).unwrap();
+        for route in self.routes.iter() {
+            writeln!(out, "  {} -> {}", route.src, route.tar).unwrap();
+            if route.routes.is_empty() {
+                writeln!(out, "    no route").unwrap();
+            }
+            for (label, hops) in route.routes.iter() {
+                writeln!(out, "    {} via {}", label, hops.join(" ")).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// Up to `count` distinct pairs of items, spread evenly, same for the same input.
+fn sample_pairs<T>(items: &[T], count: usize) -> Vec<(&T, &T)> {
+    let n = items.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let count = count.min(n * (n - 1));
+    // Step through all the ordered pairs (i, j), i != j, skipping the same number of them each time
+    let step = (n * (n - 1) / count.max(1)).max(1);
+    (0..count)
+        .map(|k| {
+            let pair = k * step;
+            let (i, j) = (pair / (n - 1), pair % (n - 1));
+            let j = if j >= i { j + 1 } else { j };
+            (&items[i], &items[j])
+        })
+        .collect()
+}
+
+#[test]
+fn test_sample_pairs() {
+    assert!(sample_pairs(&[1], 5).is_empty());
+    assert!(sample_pairs(&[1, 2, 3], 0).is_empty());
+    assert_eq!(sample_pairs(&[1, 2], 5), vec![(&1, &2), (&2, &1)]);
+    assert_eq!(sample_pairs(&[1, 2, 3, 4], 3), vec![(&1, &2), (&2, &3), (&3, &4)]);
+    let items = (0..100).collect::<Vec<_>>();
+    let pairs = sample_pairs(&items, 10);
+    assert_eq!(pairs.len(), 10);
+    assert!(pairs.iter().all(|(a, b)| a != b));
+}
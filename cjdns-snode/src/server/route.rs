@@ -1,49 +1,115 @@
 //! Route computation
 
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 use thiserror::Error;
-use tokio::task;
 
 use cjdns_core::splice::{get_encoding_form, re_encode, splice};
 use cjdns_core::{EncodingScheme, RoutingLabel};
 use cjdns_keys::CJDNS_IP6;
 
-use crate::pathsearch::{Dijkstra, GraphBuilder, GraphSolver};
+use crate::config::{CostModel, PathSolver};
+use crate::pathsearch::{BidirectionalDijkstra, Dijkstra, GraphBuilder, GraphSolver, LinkFilter, PathConstraints, PathSearchTree};
 use crate::server::nodes::{Node, Nodes};
 use crate::server::Server;
 
 pub struct Routing {
     state: RwLock<Option<RoutingState>>,
+    pending: Mutex<PendingChanges>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    rebuilds: AtomicU64,
+    updates: AtomicU64,
+    last_rebuild_duration: Mutex<Option<Duration>>,
+}
+
+/// Routing statistics snapshot.
+pub struct RoutingStats {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub rebuilds: u64,
+    pub updates: u64,
+    pub last_rebuild_duration: Option<Duration>,
 }
 
 struct RoutingState {
-    rebuild_lock: Mutex<bool>,
-    last_rebuild: Instant,
-    route_cache: HashMap<CacheKey, Arc<Mutex<Option<Route>>>>,
-    dijkstra: Dijkstra<CJDNS_IP6, f64>,
+    route_cache: HashMap<CacheKey, Arc<Mutex<Vec<Route>>>>,
+    /// Path search algorithm used by all the graphs
+    solver: PathSolver,
+    /// Routing graph for each cost model in use, built on first use
+    graphs: HashMap<CostModel, RoutingGraph>,
+    /// Cost of each graph link (by node and peer) which got cheaper since the routes of the cost model were last dropped,
+    /// as it was before that
+    cost_baselines: HashMap<CostModel, HashMap<(CJDNS_IP6, CJDNS_IP6), f64>>,
+}
+
+/// Node graph searched with the configured path solver.
+enum RoutingGraph {
+    Dijkstra(Dijkstra<CJDNS_IP6, f64>),
+    Bidirectional(BidirectionalDijkstra<CJDNS_IP6, f64>),
+}
+
+/// Node changes not yet applied to the routing graph.
+#[derive(Default)]
+struct PendingChanges {
+    /// Nodes whose set of links (or link labels) has changed, or which were added or removed.
+    links: HashSet<CJDNS_IP6>,
+    /// Nodes whose link state (hence link costs) has changed.
+    link_states: HashSet<CJDNS_IP6>,
+}
+
+impl PendingChanges {
+    fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.link_states.is_empty()
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
-struct CacheKey(CJDNS_IP6, CJDNS_IP6);
+struct CacheKey(CJDNS_IP6, CJDNS_IP6, usize, CostModel);
 
 #[derive(Clone)]
 pub struct Route {
     pub label: RoutingLabel<u64>,
-    hops: Vec<Hop>,
-    path: Vec<CJDNS_IP6>,
+    /// Hops between adjacent nodes in the `path`
+    pub(super) hops: Vec<Hop>,
+    /// Nodes along the route, from source to destination
+    pub(super) path: Vec<CJDNS_IP6>,
 }
 
 #[derive(Clone)]
-struct Hop {
-    label: RoutingLabel<u64>,
-    orig_label: RoutingLabel<u32>,
-    scheme: Arc<EncodingScheme>,
-    inverse_form_num: u8,
+pub(super) struct Hop {
+    /// Label of the link, re-encoded if needed
+    pub(super) label: RoutingLabel<u64>,
+    /// Label of the link as announced
+    pub(super) orig_label: RoutingLabel<u32>,
+    /// Encoding scheme of the node the hop starts from
+    pub(super) scheme: Arc<EncodingScheme>,
+    /// Encoding form the label was re-encoded to, to match the previous hop
+    pub(super) inverse_form_num: u8,
+}
+
+/// Restrictions on the routes being searched.
+#[derive(Clone, Default, Debug)]
+pub struct RouteConstraints {
+    /// Nodes the route must not go through (source and destination nodes are never avoided)
+    pub avoid: Vec<CJDNS_IP6>,
+
+    /// Minimum MTU of every link in the route. Links with unknown MTU are not excluded.
+    pub min_mtu: Option<u32>,
+
+    /// Maximum number of hops in the route
+    pub max_hops: Option<usize>,
+}
+
+impl RouteConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.avoid.is_empty() && self.min_mtu.is_none() && self.max_hops.is_none()
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, Error)]
@@ -55,33 +121,52 @@ pub enum RoutingError {
     RouteNotFound(CJDNS_IP6, CJDNS_IP6),
 }
 
-pub(super) fn get_route(server: Arc<Server>, src: Option<Arc<Node>>, dst: Option<Arc<Node>>) -> Result<Route, RoutingError> {
+/// Find up to `max_routes` alternative routes satisfying the constraints, the best one first according to the cost model.
+/// Alternatives not sharing any intermediate nodes with the routes found before them are preferred.
+/// On success at least one route is returned.
+pub(super) fn get_routes(
+    server: Arc<Server>,
+    src: Option<Arc<Node>>,
+    dst: Option<Arc<Node>>,
+    max_routes: usize,
+    constraints: &RouteConstraints,
+    cost_model: CostModel,
+) -> Result<Vec<Route>, RoutingError> {
     if let (Some(src), Some(dst)) = (src, dst) {
         if src == dst {
-            Ok(Route::identity())
+            Ok(vec![Route::identity()])
         } else {
             let error = RoutingError::RouteNotFound(src.ipv6.clone(), dst.ipv6.clone());
-            get_route_impl(server, src, dst).ok_or(error)
+            let routes = get_routes_impl(server, src, dst, max_routes.max(1), constraints, cost_model);
+            if routes.is_empty() {
+                Err(error)
+            } else {
+                Ok(routes)
+            }
         }
     } else {
         Err(RoutingError::NoInput)
     }
 }
 
-fn get_route_impl(server: Arc<Server>, src: Arc<Node>, dst: Arc<Node>) -> Option<Route> {
-    let (routing, cache_entry, exists) = {
-        let mut routing = server.routing.state.write();
+fn get_routes_impl(server: Arc<Server>, src: Arc<Node>, dst: Arc<Node>, max_routes: usize, constraints: &RouteConstraints, cost_model: CostModel) -> Vec<Route> {
+    let solver = server.config.read().path_solver;
+    let routing = server.routing.updated_state(&server.nodes, cost_model, solver);
 
-        // Check if routing state is not initialized yet
-        if routing.is_none() {
-            *routing = Some(RoutingState::new(build_node_graph(&server.nodes)));
-        }
+    // Constrained routes are not cached, since there are too many possible combinations
+    if !constraints.is_empty() {
+        let routing = RwLockWriteGuard::downgrade(routing);
+        let graph = routing.as_ref().expect("routing state").graph(cost_model);
+        return compute_routes(&server.nodes, graph, src, dst, max_routes, constraints);
+    }
 
+    let (routing, cache_entry, exists) = {
+        let mut routing = routing;
         let cache = &mut routing.as_mut().expect("routing state").route_cache;
-        let cache_key = CacheKey(dst.ipv6.clone(), src.ipv6.clone());
+        let cache_key = CacheKey(dst.ipv6.clone(), src.ipv6.clone(), max_routes, cost_model);
         let (exists, entry) = match cache.entry(cache_key) {
             Entry::Occupied(e) => (true, e.into_mut()),
-            Entry::Vacant(e) => (false, e.insert(Arc::new(Mutex::new(None)))),
+            Entry::Vacant(e) => (false, e.insert(Arc::new(Mutex::new(Vec::new())))),
         };
         let cache_entry = Arc::clone(&entry);
 
@@ -89,101 +174,218 @@ fn get_route_impl(server: Arc<Server>, src: Arc<Node>, dst: Arc<Node>) -> Option
     };
 
     // Need to lock this cache entry exclusively **before** we downgrade cache's exclusive lock to shared.
-    // This is needed so no other thread could see this entry in inconsistent state (possibly just created with `None` value).
+    // This is needed so no other thread could see this entry in inconsistent state (possibly just created with no routes).
     let mut cache_entry = cache_entry.lock();
 
     // Now we no longer need the exclusive lock to the cache, so downgrade it to shared lock.
     let routing = RwLockWriteGuard::downgrade(routing);
-    let routing = routing.as_ref().expect("routing state");
-
-    // Check if routing state needs rebuild, and run it in background if necessary
-    if let Some(mut locked) = routing.rebuild_lock.try_lock() {
-        let is_locked = *locked;
-        if !is_locked && routing.need_rebuild() {
-            *locked = true;
-            let server = Arc::clone(&server);
-            task::spawn(async move {
-                let d = build_node_graph(&server.nodes);
-                let mut routing = server.routing.state.write();
-                let routing = routing.as_mut().expect("routing state");
-                routing.route_cache.clear();
-                routing.dijkstra = d;
-                routing.last_rebuild = Instant::now();
-                let mut locked = routing.rebuild_lock.lock();
-                *locked = false;
-            });
-        }
-    }
+    let graph = routing.as_ref().expect("routing state").graph(cost_model);
 
     // Check if route already cached
     if exists {
+        server.routing.cache_hits.fetch_add(1, Ordering::Relaxed);
         return cache_entry.clone();
     }
+    server.routing.cache_misses.fetch_add(1, Ordering::Relaxed);
+
+    // Compute routes
+    let routes = compute_routes(&server.nodes, graph, src, dst, max_routes, constraints);
+
+    // Store routes in the cache -- now the cache entry's state is consistent
+    *cache_entry = routes.clone();
+
+    routes
+}
+
+/// Peers of every node in the routing graph, i.e. the peers with links in both directions.
+pub(super) fn node_graph_peers(server: &Server) -> Vec<(CJDNS_IP6, Vec<CJDNS_IP6>)> {
+    let (cost_model, solver) = {
+        let config = server.config.read();
+        (config.cost_model, config.path_solver)
+    };
+    let routing = server.routing.updated_state(&server.nodes, cost_model, solver);
+    let graph = routing.as_ref().expect("routing state").graph(cost_model);
+    server
+        .nodes
+        .all_ips()
+        .into_iter()
+        .map(|ip| {
+            let peers = graph.links(&ip).map(|links| links.iter().map(|(pip, _)| pip.clone()).collect()).unwrap_or_default();
+            (ip, peers)
+        })
+        .collect()
+}
 
-    // Compute route
-    let route = compute_route(&server.nodes, routing, src, dst);
+/// Best routes from a sample of source nodes to every node reachable from them, counted by `routes_through()`.
+pub(super) struct RoutesThrough {
+    /// Number of source nodes the routes were searched from
+    pub(super) sources: usize,
+    /// Number of routes found
+    pub(super) routes: usize,
+    /// Number of routes going through any of the given nodes, including the ends
+    pub(super) through: usize,
+}
 
-    // Store route in the cache -- now the cache entry's state is consistent
-    *cache_entry = route.clone();
+/// Count the best routes (according to the configured cost model) going through any of the given nodes.
+/// The routes are searched from up to `max_sources` source nodes spread evenly over the node table
+/// (every node if there are no more of them) to every node reachable from each source.
+/// The search runs on a blocking thread over a snapshot of the routing graph, so the routing state is not held meanwhile.
+pub(super) async fn routes_through(server: &Server, nodes: HashSet<CJDNS_IP6>, max_sources: usize) -> RoutesThrough {
+    let (cost_model, solver) = {
+        let config = server.config.read();
+        (config.cost_model, config.path_solver)
+    };
+    let mut ips = server.nodes.all_ips();
+    ips.sort();
+    let graph = {
+        let routing = server.routing.updated_state(&server.nodes, cost_model, solver);
+        let routing = RwLockWriteGuard::downgrade(routing);
+        let graph = routing.as_ref().expect("routing state").graph(cost_model);
+        let mut snapshot = Dijkstra::new();
+        for ip in &ips {
+            if let Some(links) = graph.links(ip) {
+                snapshot.add_node(ip.clone(), links.iter().cloned());
+            }
+        }
+        snapshot
+    };
 
-    route
+    let search = move || {
+        let step = ips.len().saturating_sub(1) / max_sources.max(1) + 1;
+        let mut res = RoutesThrough { sources: 0, routes: 0, through: 0 };
+        for src in ips.iter().step_by(step) {
+            res.sources += 1;
+            for (dst, intermediate) in graph.path_search_tree(src).paths {
+                res.routes += 1;
+                if nodes.contains(src) || nodes.contains(&dst) || intermediate.iter().any(|ip| nodes.contains(ip)) {
+                    res.through += 1;
+                }
+            }
+        }
+        res
+    };
+    tokio::task::spawn_blocking(search).await.expect("route search task failed")
 }
 
-fn build_node_graph(nodes: &Nodes) -> Dijkstra<CJDNS_IP6, f64> {
-    let mut d = Dijkstra::new();
+fn build_node_graph(nodes: &Nodes, cost_model: CostModel, solver: PathSolver) -> RoutingGraph {
+    let mut d = RoutingGraph::new(solver);
 
     for nip in nodes.all_ips() {
         let node = nodes.by_ip(&nip).unwrap();
+        let l = node_graph_links(nodes, &node, cost_model);
+        trace!("building dijkstra tree {} {:?}", nip, l);
+        d.add_node(nip, l.into_iter());
+    }
+
+    d
+}
+
+/// Graph links of a node: to every peer which has links in both directions, with the cost of the best link.
+/// Links are sorted by peer address, so the results can be compared.
+fn node_graph_links(nodes: &Nodes, node: &Node, cost_model: CostModel) -> Vec<(CJDNS_IP6, f64)> {
+    let cost_model = cost_model.model();
+    let nip = &node.ipv6;
+    let mut l = Vec::new();
+    {
         let links = node.inward_links_by_ip.lock();
-        let mut l = HashMap::new();
         for (pip, peer_links) in links.iter() {
             if peer_links.is_empty() {
                 continue; // Shouldn't happen but let's be safe
             }
             if let Some(reverse) = nodes.by_ip(pip) {
-                if reverse.inward_links_by_ip.lock().get(&nip).is_none() {
+                if reverse.inward_links_by_ip.lock().get(nip).is_none() {
                     continue;
                 }
-                // Replace with `f64::total_cmp` when it is stabilized
-                let total_cmp = |a: &f64, b: &f64| {
-                    let mut a = a.to_bits() as i64;
-                    let mut b = b.to_bits() as i64;
-                    a ^= (((a >> 63) as u64) >> 1) as i64;
-                    b ^= (((b >> 63) as u64) >> 1) as i64;
-                    a.cmp(&b)
-                };
-                let max_value = peer_links
-                    .iter()
-                    .map(|link| link.mut_state.lock().value)
-                    .max_by(total_cmp) // Replace with `f64::total_cmp` when it is stabilized (unstable as of Rust 1.46)
-                    .expect("no links") // Safe because of the above `peer_links.is_empty()` check
-                ;
-                let max_value = if max_value == 0.0 { 1e-20 } else { max_value };
-                let min_cost = max_value.recip();
-                l.insert(pip.clone(), min_cost);
+                let min_cost = cost_model.link_cost(peer_links);
+                l.push((pip.clone(), min_cost));
             }
         }
-        trace!("building dijkstra tree {} {:?}", nip, l);
-        d.add_node(nip, l.into_iter());
     }
-
-    d
+    l.sort_by(|(a, _), (b, _)| a.cmp(b));
+    l
 }
 
-fn compute_route(nodes: &Nodes, routing: &RoutingState, src: Arc<Node>, dst: Arc<Node>) -> Option<Route> {
+fn compute_routes(nodes: &Nodes, graph: &RoutingGraph, src: Arc<Node>, dst: Arc<Node>, max_routes: usize, constraints: &RouteConstraints) -> Vec<Route> {
     // We ask for the path in reverse because we build the graph in reverse.
     // Because nodes announce their own reachability instead of reachability of others.
-    let path = routing.dijkstra.reverse_path(&dst.ipv6, &src.ipv6);
+    let paths = if max_routes == 1 && constraints.is_empty() {
+        let path = graph.reverse_path(&dst.ipv6, &src.ipv6);
+        if path.is_empty() {
+            return Vec::new();
+        }
+        vec![path]
+    } else {
+        // Graph link from node A to node B stands for the link announced by node A as its inward link from B,
+        // which is also the link whose label is used in the route.
+        let min_mtu = constraints.min_mtu.unwrap_or(0);
+        let mtu_filter = |a: &CJDNS_IP6, b: &CJDNS_IP6| link_mtu(nodes, a, b).map(|mtu| mtu == 0 || mtu >= min_mtu).unwrap_or(false);
+        let path_constraints = PathConstraints {
+            excluded_nodes: constraints.avoid.iter().filter(|&ip| *ip != src.ipv6 && *ip != dst.ipv6).cloned().collect(),
+            max_hops: constraints.max_hops,
+            link_filter: constraints.min_mtu.map(|_| &mtu_filter as LinkFilter<_>),
+        };
 
-    if path.is_empty() {
-        return None;
-    }
+        // Look at more paths than requested, so there is a chance to find node-disjoint ones
+        const CANDIDATES_PER_ROUTE: usize = 3;
+        let candidates = if max_routes == 1 { 1 } else { max_routes * CANDIDATES_PER_ROUTE };
+        let paths = graph.k_shortest_paths(&dst.ipv6, &src.ipv6, candidates, &path_constraints);
+        let paths = paths
+            .into_iter()
+            .map(|mut path| {
+                path.reverse();
+                path
+            })
+            .collect();
+        select_alternatives(paths, max_routes)
+    };
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let (label, hops) = compute_routing_label(nodes, &path)?;
+            Some(Route { label, hops, path })
+        })
+        .collect()
+}
 
-    let (label, hops) = compute_routing_label(nodes, &path)?;
+/// MTU of the first link announced by the node as its inward link from the peer, which is the link used in routes.
+fn link_mtu(nodes: &Nodes, node_ip: &CJDNS_IP6, peer_ip: &CJDNS_IP6) -> Option<u32> {
+    let node = nodes.by_ip(node_ip)?;
+    let links = node.inward_links_by_ip.lock();
+    let link = links.get(peer_ip)?.first()?;
+    let mtu = link.mut_state.lock().mtu;
+    Some(mtu)
+}
 
-    let route = Route { label, hops, path };
+/// Pick up to `count` paths out of the given ones (sorted cheapest first).
+/// Paths which have no intermediate nodes in common with the already picked ones are preferred,
+/// the rest are picked in the original order.
+fn select_alternatives<T: PartialEq>(paths: Vec<Vec<T>>, count: usize) -> Vec<Vec<T>> {
+    fn intermediate<T>(path: &[T]) -> &[T] {
+        if path.len() > 2 {
+            &path[1..path.len() - 1]
+        } else {
+            &[]
+        }
+    }
 
-    Some(route)
+    let mut picked = Vec::<Vec<T>>::new();
+    let mut rest = Vec::new();
+    for path in paths {
+        if picked.len() >= count {
+            break;
+        }
+        let disjoint = picked.iter().all(|p| intermediate(p).iter().all(|n| !intermediate(&path).contains(n)));
+        if disjoint {
+            picked.push(path);
+        } else {
+            rest.push(path);
+        }
+    }
+
+    let missing = count.saturating_sub(picked.len());
+    picked.extend(rest.into_iter().take(missing));
+    picked
 }
 
 fn compute_routing_label(nodes: &Nodes, rev_path: &[CJDNS_IP6]) -> Option<(RoutingLabel<u64>, Vec<Hop>)> {
@@ -244,23 +446,355 @@ impl Route {
 
 impl Routing {
     pub(super) fn new() -> Self {
-        Routing { state: RwLock::new(None) }
+        Routing {
+            state: RwLock::new(None),
+            pending: Mutex::new(PendingChanges::default()),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            rebuilds: AtomicU64::new(0),
+            updates: AtomicU64::new(0),
+            last_rebuild_duration: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn stats(&self) -> RoutingStats {
+        RoutingStats {
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            rebuilds: self.rebuilds.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            last_rebuild_duration: *self.last_rebuild_duration.lock(),
+        }
+    }
+
+    /// Notify that the node was added, reset or forgotten, or its links were added, replaced or withdrawn.
+    /// The change is applied to the routing graph before the next route query.
+    pub(super) fn links_changed(&self, node_ip: &CJDNS_IP6) {
+        self.pending.lock().links.insert(node_ip.clone());
+    }
+
+    /// Notify that the link state of the node's links was updated, so their costs may have changed.
+    pub(super) fn link_states_changed(&self, node_ip: &CJDNS_IP6) {
+        self.pending.lock().link_states.insert(node_ip.clone());
+    }
+
+    fn take_pending(&self) -> PendingChanges {
+        std::mem::take(&mut *self.pending.lock())
+    }
+
+    /// Lock routing state exclusively, applying pending changes to it,
+    /// and building the graph for the given cost model if it is not built yet.
+    /// The state is built anew if it uses a different path solver.
+    fn updated_state(&self, nodes: &Nodes, cost_model: CostModel, solver: PathSolver) -> RwLockWriteGuard<'_, Option<RoutingState>> {
+        let mut routing = self.state.write();
+        if routing.as_ref().map(|state| state.solver != solver).unwrap_or(false) {
+            *routing = None;
+        }
+        let state = routing.get_or_insert_with(|| RoutingState::new(solver));
+
+        let pending = self.take_pending();
+        if !pending.is_empty() && !state.graphs.is_empty() {
+            state.apply_changes(nodes, pending);
+            self.updates.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Changes made before this point are already reflected in the newly built graph
+        if !state.graphs.contains_key(&cost_model) {
+            let graph = self.build_node_graph_timed(nodes, cost_model, solver);
+            state.graphs.insert(cost_model, graph);
+        }
+
+        routing
+    }
+
+    /// Build node graph, recording how long it took.
+    fn build_node_graph_timed(&self, nodes: &Nodes, cost_model: CostModel, solver: PathSolver) -> RoutingGraph {
+        let start = Instant::now();
+        let d = build_node_graph(nodes, cost_model, solver);
+        *self.last_rebuild_duration.lock() = Some(start.elapsed());
+        self.rebuilds.fetch_add(1, Ordering::Relaxed);
+        d
     }
 }
 
 impl RoutingState {
-    pub(super) fn new(d: Dijkstra<CJDNS_IP6, f64>) -> Self {
+    pub(super) fn new(solver: PathSolver) -> Self {
         RoutingState {
-            rebuild_lock: Mutex::new(false),
-            last_rebuild: Instant::now(),
             route_cache: HashMap::new(),
-            dijkstra: d,
+            solver,
+            graphs: HashMap::new(),
+            cost_baselines: HashMap::new(),
+        }
+    }
+
+    /// Routing graph for the cost model, which must be already built.
+    fn graph(&self, cost_model: CostModel) -> &RoutingGraph {
+        self.graphs.get(&cost_model).expect("routing graph not built")
+    }
+
+    /// Update graph links of the changed nodes and their peers in every graph,
+    /// then drop cached routes going through any node whose links have changed.
+    /// If any graph link was added, or got noticeably cheaper than it was when the routes were cached,
+    /// a better route may now exist between any nodes, so all the routes cached for that cost model are dropped.
+    fn apply_changes(&mut self, nodes: &Nodes, pending: PendingChanges) {
+        // Graph link to a peer exists only if there are links in both directions,
+        // so a change of the node's links may affect its peers' graph links as well.
+        let mut affected = HashSet::new();
+        for nip in pending.links.iter().chain(pending.link_states.iter()) {
+            for graph in self.graphs.values() {
+                if let Some(links) = graph.links(nip) {
+                    affected.extend(links.iter().map(|(pip, _)| pip.clone()));
+                }
+            }
+            if let Some(node) = nodes.by_ip(nip) {
+                affected.extend(node.inward_links_by_ip.lock().keys().cloned());
+            }
+            affected.insert(nip.clone());
+        }
+
+        // Labels used to build the route are taken from the links, so any link change invalidates the route
+        let mut changed = pending.links;
+        let mut improved = HashSet::new();
+        for nip in affected {
+            let node = nodes.by_ip(&nip);
+            for (&cost_model, graph) in self.graphs.iter_mut() {
+                match node.as_ref() {
+                    Some(node) => {
+                        let new_links = node_graph_links(nodes, node, cost_model);
+                        let old_links = graph.links(&nip);
+                        if old_links != Some(new_links.as_slice()) {
+                            let baselines = self.cost_baselines.entry(cost_model).or_default();
+                            if links_improved(&nip, old_links.unwrap_or(&[]), &new_links, baselines) {
+                                improved.insert(cost_model);
+                            }
+                            trace!("updating dijkstra tree {} {:?}", nip, new_links);
+                            graph.add_node(nip.clone(), new_links);
+                            changed.insert(nip.clone());
+                        }
+                    }
+                    None => {
+                        if graph.links(&nip).is_some() {
+                            graph.remove_node(&nip);
+                            changed.insert(nip.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        if changed.is_empty() {
+            return;
+        }
+
+        for cost_model in improved.iter() {
+            self.cost_baselines.remove(cost_model);
+        }
+
+        // Missing routes may now be found, so these are dropped as well
+        self.route_cache.retain(|CacheKey(_, _, _, cost_model), entry| {
+            if improved.contains(cost_model) {
+                return false;
+            }
+            let routes = entry.lock();
+            !routes.is_empty() && routes.iter().all(|route| !route.path.iter().any(|nip| changed.contains(nip)))
+        });
+    }
+}
+
+/// Whether any of the new graph links of a node is missing from its old links, or has got cheaper by more than
+/// `IMPROVEMENT_THRESHOLD` of its baseline cost. The baseline is the cost the link had before it first got cheaper,
+/// recorded in `baselines` on the way. Link costs (under the `value` cost model especially) go down a little
+/// with most link state updates, so small improvements are let to add up before dropping all the cached routes.
+/// This way a cached route is never more than `1 / (1 - IMPROVEMENT_THRESHOLD)` times as costly as the best one.
+fn links_improved(
+    node_ip: &CJDNS_IP6,
+    old_links: &[(CJDNS_IP6, f64)],
+    new_links: &[(CJDNS_IP6, f64)],
+    baselines: &mut HashMap<(CJDNS_IP6, CJDNS_IP6), f64>,
+) -> bool {
+    const IMPROVEMENT_THRESHOLD: f64 = 0.2;
+
+    new_links.iter().any(|(pip, cost)| match old_links.iter().find(|(old_pip, _)| old_pip == pip) {
+        Some((_, old_cost)) if cost < old_cost => {
+            let baseline = baselines.entry((node_ip.clone(), pip.clone())).or_insert(*old_cost);
+            *cost < *baseline * (1.0 - IMPROVEMENT_THRESHOLD)
+        }
+        Some(_) => false,
+        None => true,
+    })
+}
+
+impl RoutingGraph {
+    fn new(solver: PathSolver) -> Self {
+        match solver {
+            PathSolver::Dijkstra => RoutingGraph::Dijkstra(Dijkstra::new()),
+            PathSolver::Bidirectional => RoutingGraph::Bidirectional(BidirectionalDijkstra::new()),
+        }
+    }
+}
+
+impl GraphBuilder<CJDNS_IP6, f64> for RoutingGraph {
+    fn add_node<I: IntoIterator<Item = (CJDNS_IP6, f64)>>(&mut self, node_tag: CJDNS_IP6, links: I) {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.add_node(node_tag, links),
+            RoutingGraph::Bidirectional(g) => g.add_node(node_tag, links),
+        }
+    }
+
+    fn remove_node(&mut self, node_tag: &CJDNS_IP6) {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.remove_node(node_tag),
+            RoutingGraph::Bidirectional(g) => g.remove_node(node_tag),
+        }
+    }
+
+    fn links(&self, node_tag: &CJDNS_IP6) -> Option<&[(CJDNS_IP6, f64)]> {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.links(node_tag),
+            RoutingGraph::Bidirectional(g) => g.links(node_tag),
         }
     }
+}
 
-    pub(super) fn need_rebuild(&self) -> bool {
-        const REBUILD_INTERVAL: Duration = Duration::from_secs(3);
-        let now = Instant::now();
-        self.last_rebuild + REBUILD_INTERVAL < now
+impl GraphSolver<CJDNS_IP6, f64> for RoutingGraph {
+    fn path(&self, from: &CJDNS_IP6, to: &CJDNS_IP6) -> Vec<CJDNS_IP6> {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.path(from, to),
+            RoutingGraph::Bidirectional(g) => g.path(from, to),
+        }
     }
+
+    fn reverse_path(&self, from: &CJDNS_IP6, to: &CJDNS_IP6) -> Vec<CJDNS_IP6> {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.reverse_path(from, to),
+            RoutingGraph::Bidirectional(g) => g.reverse_path(from, to),
+        }
+    }
+
+    fn k_shortest_paths(&self, from: &CJDNS_IP6, to: &CJDNS_IP6, k: usize, constraints: &PathConstraints<CJDNS_IP6>) -> Vec<Vec<CJDNS_IP6>> {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.k_shortest_paths(from, to, k, constraints),
+            RoutingGraph::Bidirectional(g) => g.k_shortest_paths(from, to, k, constraints),
+        }
+    }
+
+    fn path_search_tree(&self, start: &CJDNS_IP6) -> PathSearchTree<CJDNS_IP6> {
+        match self {
+            RoutingGraph::Dijkstra(g) => g.path_search_tree(start),
+            RoutingGraph::Bidirectional(g) => g.path_search_tree(start),
+        }
+    }
+}
+
+#[test]
+fn test_select_alternatives() {
+    let paths = vec![vec![1, 2, 3, 9], vec![1, 2, 4, 9], vec![1, 5, 9], vec![1, 6, 2, 9], vec![1, 7, 9]];
+    assert_eq!(select_alternatives(paths.clone(), 1), vec![vec![1, 2, 3, 9]]);
+    assert_eq!(select_alternatives(paths.clone(), 2), vec![vec![1, 2, 3, 9], vec![1, 5, 9]]);
+    assert_eq!(select_alternatives(paths.clone(), 3), vec![vec![1, 2, 3, 9], vec![1, 5, 9], vec![1, 7, 9]]);
+    assert_eq!(
+        select_alternatives(paths.clone(), 4),
+        vec![vec![1, 2, 3, 9], vec![1, 5, 9], vec![1, 7, 9], vec![1, 2, 4, 9]],
+    );
+    assert_eq!(select_alternatives(paths.clone(), 10).len(), 5);
+}
+
+#[test]
+fn test_route_cache_shortcut() {
+    use std::time::SystemTime;
+
+    use crate::server::tests::{add_numbered_node, link_nodes, test_server};
+    use crate::utils::clock::ManualClock;
+
+    let server = Arc::new(test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH))));
+    let route = |src: &Arc<Node>, dst: &Arc<Node>| {
+        let routes = get_routes(server.clone(), Some(src.clone()), Some(dst.clone()), 1, &RouteConstraints::default(), CostModel::HopCount);
+        routes.expect("no route").remove(0).path
+    };
+
+    // Long way from 1 to 5, and two nodes one hop away from either end, not linked to each other yet
+    let nodes = (1..=7).map(|n| add_numbered_node(&server, n, 21)).collect::<Vec<_>>();
+    let ips = |ns: &[usize]| ns.iter().map(|&n| nodes[n - 1].ipv6.clone()).collect::<Vec<_>>();
+    for pair in nodes[..5].windows(2) {
+        link_nodes(&server, &pair[0], &pair[1]);
+    }
+    link_nodes(&server, &nodes[0], &nodes[5]);
+    link_nodes(&server, &nodes[6], &nodes[4]);
+    assert_eq!(route(&nodes[0], &nodes[4]), ips(&[1, 2, 3, 4, 5]));
+    assert_eq!(route(&nodes[0], &nodes[4]), ips(&[1, 2, 3, 4, 5]));
+    assert_eq!(server.routing.stats().cache_hits, 1);
+
+    // The shortcut doesn't touch the cached route, yet the next answer takes it
+    link_nodes(&server, &nodes[5], &nodes[6]);
+    assert_eq!(route(&nodes[0], &nodes[4]), ips(&[1, 6, 7, 5]));
+}
+
+#[test]
+fn test_route_cache_link_state_updates() {
+    use std::time::SystemTime;
+
+    use crate::server::tests::{add_numbered_node, link_nodes, test_server};
+    use crate::utils::clock::ManualClock;
+
+    let server = Arc::new(test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH))));
+    let set_value = |a: &Node, b: &Node, value: f64| {
+        for &(node, peer) in [(a, b), (b, a)].iter() {
+            node.inward_links_by_ip.lock()[&peer.ipv6][0].mut_state.lock().value = value;
+            server.routing.link_states_changed(&node.ipv6);
+        }
+    };
+    let hits = || server.routing.stats().cache_hits;
+    let route = |src: &Arc<Node>, dst: &Arc<Node>| {
+        let routes = get_routes(server.clone(), Some(src.clone()), Some(dst.clone()), 1, &RouteConstraints::default(), CostModel::Value);
+        routes.expect("no route").remove(0).path
+    };
+
+    // Chain 1-2-3-4-5, the route from 1 to 3 doesn't use the link between 4 and 5
+    let nodes = (1..=5).map(|n| add_numbered_node(&server, n, 21)).collect::<Vec<_>>();
+    for pair in nodes.windows(2) {
+        link_nodes(&server, &pair[0], &pair[1]);
+        set_value(&pair[0], &pair[1], 1.0);
+    }
+    let cached = route(&nodes[0], &nodes[2]);
+
+    // Link state updates make the other link a bit cheaper every time, and the route stays cached
+    for i in 1..=10 {
+        set_value(&nodes[3], &nodes[4], 1.0 + 0.01 * i as f64);
+        let hits_before = hits();
+        assert_eq!(route(&nodes[0], &nodes[2]), cached);
+        assert_eq!(hits(), hits_before + 1);
+    }
+
+    // Once the link gets much cheaper than it was, better routes may exist, so the route is searched again
+    set_value(&nodes[3], &nodes[4], 2.0);
+    let hits_before = hits();
+    assert_eq!(route(&nodes[0], &nodes[2]), cached);
+    assert_eq!(hits(), hits_before);
+}
+
+#[tokio::test]
+async fn test_routes_through() {
+    use std::time::SystemTime;
+
+    use crate::server::tests::{add_numbered_node, link_nodes, test_server};
+    use crate::utils::clock::ManualClock;
+
+    // Chain 1-2-3-4 and separate node 5
+    let server = test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH)));
+    let nodes = (1..=5).map(|n| add_numbered_node(&server, n, 21)).collect::<Vec<_>>();
+    for pair in nodes[..4].windows(2) {
+        link_nodes(&server, &pair[0], &pair[1]);
+    }
+
+    // Routes between all 4 connected nodes both ways, 10 of them through node 2 (ends included), 6 through node 1
+    let through = |ns: &[usize], max_sources| routes_through(&server, ns.iter().map(|&n| nodes[n - 1].ipv6.clone()).collect(), max_sources);
+    let res = through(&[2], 10).await;
+    assert_eq!((res.sources, res.routes, res.through), (5, 12, 10));
+    let res = through(&[1], 10).await;
+    assert_eq!((res.sources, res.routes, res.through), (5, 12, 6));
+    assert_eq!(through(&[5], 10).await.through, 0);
+
+    // Sources spread over the node table: 1 and 4
+    let res = through(&[2], 2).await;
+    assert_eq!((res.sources, res.routes, res.through), (2, 6, 5));
 }
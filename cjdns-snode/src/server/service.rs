@@ -9,30 +9,43 @@ use tokio::{select, time};
 
 use cjdns_admin::msgs::{Empty, GenericResponsePayload};
 use cjdns_admin::{ArgValues, Connection, ReturnValue};
+use cjdns_ann::AnnHash;
 use cjdns_bencode::BValue;
 use cjdns_hdr::RouteHeader;
 use cjdns_keys::{CJDNSPublicKey, CJDNS_IP6};
 use cjdns_sniff::{Content, ContentType, Message, ReceiveError, Sniffer};
 
-use crate::server::route::get_route;
+use crate::config::CostModel;
+use crate::server::route::{get_routes, RouteConstraints};
 use crate::server::service::core_node_info::try_parse_encoding_scheme;
-use crate::server::Server;
+use crate::server::{ReplyError, Server};
 use crate::utils::node::parse_node_name;
-use crate::utils::timestamp::{current_timestamp, mktime};
+use crate::utils::timestamp::{make_timestamp, mktime};
 
 use self::core_node_info::CoreNodeInfoPayload;
 
+/// How to reach the local node (router).
+#[derive(Clone, Default)]
+struct LocalRouter {
+    /// Admin API connection options, `None` to use the cjdnsadmin config file
+    admin_opts: Option<cjdns_admin::Opts>,
+    /// Where to send the replies to the subnodes, `None` for the address intercepted by the router itself
+    reply_dest: Option<String>,
+}
+
 pub(super) async fn service_task(server: Arc<Server>) {
-    loop {
-        let res = do_service(server.clone()).await;
+    let local_router = LocalRouter::default();
+    while !server.shutdown.is_triggered() {
+        let res = do_service(server.clone(), &local_router).await;
         if let Err(err) = res {
             error!("Failed to service local node: {}. Reconecting...", err);
         }
     }
+    server.service_stopped.notify();
 }
 
-async fn do_service(server: Arc<Server>) -> Result<(), Error> {
-    let mut cjdns = cjdns_admin::connect(None).await?;
+async fn do_service(server: Arc<Server>, local_router: &LocalRouter) -> Result<(), Error> {
+    let mut cjdns = cjdns_admin::connect(local_router.admin_opts.clone()).await?;
 
     // Querying local node info
     let node_info = cjdns.invoke::<_, CoreNodeInfoPayload>("Core_nodeInfo", Empty {}).await?;
@@ -51,21 +64,30 @@ async fn do_service(server: Arc<Server>) -> Result<(), Error> {
     debug!("Got selfNode");
 
     // Starting to sniff traffic
-    let sniffer = Sniffer::sniff_traffic(cjdns.clone(), ContentType::Cjdht).await?;
+    let mut sniffer = Sniffer::sniff_traffic(cjdns.clone(), ContentType::Cjdht).await?;
 
-    select! {
-        res = handle_subnode_messages(sniffer, server) => res,
+    let res = select! {
+        res = handle_subnode_messages(&mut sniffer, server.clone(), local_router.reply_dest.as_deref()) => res,
         res = check_connection_alive(cjdns) => res,
+        _ = server.shutdown.wait() => Ok(()),
+    };
+
+    // Unregister the handler, so the router doesn't keep sending traffic to a socket nobody reads
+    if let Err(err) = sniffer.disconnect().await {
+        warn!("Failed to unregister from the local node: {}", err);
     }
+
+    res
 }
 
-async fn handle_subnode_messages(mut sniffer: Sniffer, server: Arc<Server>) -> Result<(), Error> {
+async fn handle_subnode_messages(sniffer: &mut Sniffer, server: Arc<Server>, reply_dest: Option<&str>) -> Result<(), Error> {
     loop {
         match sniffer.receive().await {
             Ok(msg) => {
+                server.stats.sniffer_msg_received(server.clock.instant());
                 let ret_msg_opt = on_subnode_message(server.clone(), msg).await?;
                 if let Some(ret_msg) = ret_msg_opt {
-                    sniffer.send(ret_msg, None).await?;
+                    sniffer.send(ret_msg, reply_dest).await?;
                 }
             }
             Err(err @ ReceiveError::SocketError(_)) => {
@@ -158,13 +180,11 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
 
     let txid = content_benc.get_dict_value("txid").ok().flatten();
 
-    server.mut_state.lock().current_node = route_header.ip6.clone();
+    let node_ip = route_header.ip6.clone().expect("ip6"); // Safe because of the check above
 
-    let debug_noisy = {
-        let mut ms = server.mut_state.lock();
-        ms.current_node = route_header.ip6.clone();
-        ms.debug_node.is_some() && ms.debug_node == route_header.ip6
-    };
+    server.mut_state.lock().current_node = Some(node_ip.clone());
+
+    let debug_noisy = server.debug_trace.is_traced(&node_ip, server.clock.instant());
 
     let self_version = if let Some(self_node) = server.mut_state.lock().self_node.as_ref() {
         self_node.version as i64
@@ -193,8 +213,10 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
             let src_ip = CJDNS_IP6::try_from(src.as_slice()).map_err(|e| anyhow!("bad 'src' address: {}", e))?;
             let tar_ip = CJDNS_IP6::try_from(tar.as_slice()).map_err(|e| anyhow!("bad 'tar' address: {}", e))?;
 
+            let constraints = parse_route_constraints(&content_benc)?;
+
             if debug_noisy {
-                debug!("gr {} -> {}", src_ip, tar_ip);
+                server.trace_node(&node_ip, format!("gr {} -> {} {:?}", src_ip, tar_ip, constraints));
             }
 
             let src = server.nodes.by_ip(&src_ip);
@@ -204,28 +226,37 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
                 .set_dict()
                 .add_dict_entry_opt("txid", txid)
                 .add_dict_entry("p", |b| b.set_int(self_version))
-                .add_dict_entry("recvTime", |b| b.set_int(current_timestamp() as i64));
+                .add_dict_entry("recvTime", |b| b.set_int(make_timestamp(server.clock.now()) as i64));
 
-            let route = get_route(server.clone(), src.clone(), tar.clone());
+            let (max_routes, default_cost_model) = {
+                let config = server.config.read();
+                (config.max_routes, config.cost_model)
+            };
+            let cost_model = parse_cost_model(&content_benc)?.unwrap_or(default_cost_model);
+            let routes = get_routes(server.clone(), src.clone(), tar.clone(), max_routes, &constraints, cost_model);
 
-            let res = if let (Ok(route), Some(tar)) = (route, tar) {
+            let res = if let (Ok(routes), Some(tar)) = (routes, tar) {
                 res
-                    // List of nodes (one entry - the destination).
+                    // List of nodes (one entry per alternative route - the destination).
                     // Each node represented as its public key + routing label.
                     .add_dict_entry("n", |b| {
-                        let label_bits = route.label.bits().to_be_bytes();
-                        let mut buf = Vec::with_capacity(CJDNSPublicKey::SIZE + route.label.size());
-                        buf.extend_from_slice(&tar.key);
-                        buf.extend_from_slice(&label_bits);
+                        let mut buf = Vec::with_capacity(routes.len() * (CJDNSPublicKey::SIZE + 8));
+                        for route in routes.iter() {
+                            let label_bits = route.label.bits().to_be_bytes();
+                            buf.extend_from_slice(&tar.key);
+                            buf.extend_from_slice(&label_bits);
+                        }
                         b.set_bytes(buf)
                     })
-                    // List of nodes' protocol version (one entry - the destination).
+                    // List of nodes' protocol version (one entry per alternative route - the destination).
                     // The first byte is the number of bytes taken by each version in the list (always 1 for now),
                     // followed by the versions themselves, encoded in big endian.
                     .add_dict_entry("np", |b| {
-                        let mut buf = Vec::with_capacity(2);
+                        let mut buf = Vec::with_capacity(1 + routes.len());
                         buf.push(1); // Number of bytes taken by each version
-                        buf.push(tar.version as u8); // Version as 1-byte integer
+                        for _ in routes.iter() {
+                            buf.push(tar.version as u8); // Version as 1-byte integer
+                        }
                         b.set_bytes(buf)
                     })
             } else {
@@ -238,16 +269,35 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
         "ann" if content_benc.has_dict_entry("ann") => {
             let ann = content_benc.get_dict_value_bytes("ann").expect("benc 'ann' entry"); // Safe because of the check above
 
-            let (state_hash, reply_err) = server.handle_announce_impl(ann, true, Some(debug_noisy)).await?;
+            let throttled = server
+                .rate_limiter
+                .as_ref()
+                .map(|rate_limiter| !rate_limiter.try_accept(&node_ip, server.clock.instant()))
+                .unwrap_or(false);
+
+            let (state_hash, reply_err) = if throttled {
+                if debug_noisy {
+                    server.trace_node(&node_ip, format!("ann from {} throttled", node_ip));
+                }
+                // Reply with the current state hash, so the node will retry later
+                let state_hash = server.nodes.by_ip(&node_ip).and_then(|node| node.mut_state.read().state_hash.clone());
+                (state_hash.unwrap_or_else(|| AnnHash(vec![0; 64])), ReplyError::RateLimited)
+            } else {
+                server.handle_announce_impl(ann, true, Some(debug_noisy)).await.map_err(|e| {
+                    server.stats.ann_failed();
+                    e
+                })?
+            };
+            server.stats.ann_processed(&reply_err);
             if debug_noisy {
-                debug!("reply: {:?}", hex::encode(state_hash.bytes()));
+                server.trace_node(&node_ip, format!("reply: {:?}", hex::encode(state_hash.bytes())));
             }
 
             let res = BValue::builder()
                 .set_dict()
                 .add_dict_entry_opt("txid", txid)
                 .add_dict_entry("p", |b| b.set_int(self_version))
-                .add_dict_entry("recvTime", |b| b.set_int(current_timestamp() as i64))
+                .add_dict_entry("recvTime", |b| b.set_int(make_timestamp(server.clock.now()) as i64))
                 .add_dict_entry("stateHash", |b| b.set_bytes(state_hash.into_inner()))
                 .add_dict_entry("error", |b| b.set_str(reply_err.to_string()))
                 .build();
@@ -257,12 +307,12 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
 
         "pn" => {
             if debug_noisy {
-                debug!("pn");
+                server.trace_node(&node_ip, "pn".to_string());
             }
             let mut res = BValue::builder()
                 .set_dict()
                 .add_dict_entry_opt("txid", txid)
-                .add_dict_entry("recvTime", |b| b.set_int(current_timestamp() as i64))
+                .add_dict_entry("recvTime", |b| b.set_int(make_timestamp(server.clock.now()) as i64))
                 .add_dict_entry("p", |b| b.set_int(self_version))
                 .add_dict_entry("stateHash", |b| b.set_bytes([0u8; 64].to_vec()));
 
@@ -290,6 +340,48 @@ async fn on_subnode_message_impl(server: Arc<Server>, route_header: RouteHeader,
     Ok(res)
 }
 
+/// Parse optional route constraints from the `gr` request:
+/// `avoid` - concatenated IPv6 addresses of the nodes to avoid, `mtu` - minimum MTU, `hops` - maximum number of hops.
+fn parse_route_constraints(content_benc: &BValue) -> Result<RouteConstraints, Error> {
+    let mut constraints = RouteConstraints::default();
+
+    if content_benc.has_dict_entry("avoid") {
+        let avoid = content_benc.get_dict_value_bytes("avoid").map_err(|_| anyhow!("bad 'avoid' entry"))?;
+        if avoid.len() % 16 != 0 {
+            return Err(anyhow!("bad 'avoid' entry length {}", avoid.len()));
+        }
+        for ip in avoid.chunks(16) {
+            let ip = CJDNS_IP6::try_from(ip).map_err(|e| anyhow!("bad 'avoid' address: {}", e))?;
+            constraints.avoid.push(ip);
+        }
+    }
+
+    let get_int = |key: &str| -> Result<Option<i64>, Error> {
+        match content_benc.get_dict_value(key) {
+            Ok(Some(value)) => value.as_int().map(Some).map_err(|_| anyhow!("bad '{}' entry", key)),
+            _ => Ok(None),
+        }
+    };
+    if let Some(mtu) = get_int("mtu")? {
+        constraints.min_mtu = Some(u32::try_from(mtu).map_err(|_| anyhow!("bad 'mtu' value {}", mtu))?);
+    }
+    if let Some(hops) = get_int("hops")? {
+        constraints.max_hops = Some(usize::try_from(hops).map_err(|_| anyhow!("bad 'hops' value {}", hops))?);
+    }
+
+    Ok(constraints)
+}
+
+/// Parse optional link cost model name from the `cost` entry of the `gr` request.
+fn parse_cost_model(content_benc: &BValue) -> Result<Option<CostModel>, Error> {
+    if !content_benc.has_dict_entry("cost") {
+        return Ok(None);
+    }
+    let name = content_benc.get_dict_value_str("cost").map_err(|_| anyhow!("bad 'cost' entry"))?;
+    let cost_model = name.parse().map_err(|e| anyhow!("{}", e))?;
+    Ok(Some(cost_model))
+}
+
 mod core_node_info {
     use std::convert::{TryFrom, TryInto};
 
@@ -335,3 +427,72 @@ mod core_node_info {
         Ok(encoding_scheme)
     }
 }
+
+#[tokio::test]
+async fn test_service_mock_router() {
+    use std::path::PathBuf;
+
+    use cjdns_admin::mock::{MockConfig, MockRouter};
+    use cjdns_hdr::DataHeader;
+
+    use crate::config::Config;
+    use crate::peer::create_peers;
+    use crate::utils::clock::SystemClock;
+
+    let mut router = MockRouter::start(MockConfig::default()).await.expect("start mock router");
+    let local_router = LocalRouter {
+        admin_opts: Some(router.opts()),
+        reply_dest: Some(router.inject_addr().to_string()),
+    };
+    let clock = Arc::new(SystemClock);
+    let (peers, _announces) = create_peers(clock.clone());
+    let server = Arc::new(Server::new(Arc::new(peers), clock, PathBuf::new(), Config::default()));
+
+    // Ping from a subnode: route header, data header and bencoded content
+    let mut packet = hex::decode(
+        "a331ebbed8d92ac03b10efed3e389cd0c6ec7331a72dbde198476c5eb4d14a1f0000000000000013004800000000000001000000fc928136dc1fe6e04ef6a6dd7187b85f",
+    )
+    .expect("bad route header");
+    let data_header = DataHeader {
+        content_type: ContentType::Cjdht,
+        ..DataHeader::default()
+    };
+    packet.extend(data_header.serialize().expect("data header"));
+    let query = BValue::builder()
+        .set_dict()
+        .add_dict_entry("sq", |b| b.set_str("pn".to_string()))
+        .add_dict_entry("txid", |b| b.set_bytes(vec![1, 2, 3]))
+        .add_dict_entry("p", |b| b.set_int(20))
+        .build();
+    packet.extend(query.encode().expect("query"));
+
+    let exchange = async {
+        while router.handlers().is_empty() {
+            time::delay_for(Duration::from_millis(10)).await;
+        }
+        router.inject(ContentType::Cjdht as u32, &packet).await.expect("inject");
+        let reply = router.capture().await.expect("capture");
+        server.shutdown.trigger();
+        reply
+    };
+    let service = do_service(server.clone(), &local_router);
+    let (res, reply) = time::timeout(Duration::from_secs(10), futures::future::join(service, exchange))
+        .await
+        .expect("no reply from the service");
+    res.expect("service failed");
+
+    // Shutting down unregisters the sniffer
+    assert!(router.handlers().is_empty());
+
+    let self_node = server.mut_state.lock().self_node.clone().expect("self node");
+    assert_eq!(self_node.version, 21);
+    assert!(router.calls().contains(&"Core_nodeInfo".to_string()));
+
+    let route_header = RouteHeader::parse(&reply[..RouteHeader::SIZE]).expect("route header");
+    assert_eq!(route_header.version, 20);
+    assert_eq!(route_header.switch_header.label_shift, 0);
+    let reply = BValue::decode(&reply[RouteHeader::SIZE + DataHeader::SIZE..]).expect("reply content");
+    assert_eq!(reply.get_dict_value_bytes("txid"), Ok(vec![1, 2, 3]));
+    assert_eq!(reply.get_dict_value("p").ok().flatten().and_then(|p| p.as_int().ok()), Some(21));
+    assert_eq!(reply.get_dict_value_bytes("stateHash"), Ok(vec![0; 64]));
+}
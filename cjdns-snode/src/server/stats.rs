@@ -0,0 +1,45 @@
+//! Server statistics
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::server::ReplyError;
+
+pub(super) struct Stats {
+    /// Number of processed announcements by reply error
+    anns_processed: Mutex<BTreeMap<String, u64>>,
+    /// Time of the last message received from the local cjdns router
+    last_sniffer_msg: Mutex<Option<Instant>>,
+}
+
+impl Stats {
+    pub(super) fn new() -> Self {
+        Stats {
+            anns_processed: Mutex::new(BTreeMap::new()),
+            last_sniffer_msg: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn ann_processed(&self, reply_err: &ReplyError) {
+        *self.anns_processed.lock().entry(reply_err.to_string()).or_insert(0) += 1;
+    }
+
+    /// Count announcement which failed to be processed due to internal error.
+    pub(super) fn ann_failed(&self) {
+        *self.anns_processed.lock().entry("internal_error".to_string()).or_insert(0) += 1;
+    }
+
+    pub(super) fn anns_processed(&self) -> BTreeMap<String, u64> {
+        self.anns_processed.lock().clone()
+    }
+
+    pub(super) fn sniffer_msg_received(&self, now: Instant) {
+        *self.last_sniffer_msg.lock() = Some(now);
+    }
+
+    pub(super) fn last_sniffer_msg_age(&self, now: Instant) -> Option<Duration> {
+        self.last_sniffer_msg.lock().map(|t| now.saturating_duration_since(t))
+    }
+}
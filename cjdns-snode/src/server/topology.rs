@@ -0,0 +1,322 @@
+//! Network topology export in standard graph formats
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use cjdns_core::{EncodingScheme, RoutingLabel};
+
+use crate::server::nodes::Nodes;
+
+/// Graph file format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(super) enum TopologyFormat {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML (XML)
+    GraphMl,
+    /// Node-link JSON graph, as read by networkx `node_link_graph()`
+    Json,
+}
+
+impl FromStr for TopologyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(TopologyFormat::Dot),
+            "graphml" => Ok(TopologyFormat::GraphMl),
+            "json" => Ok(TopologyFormat::Json),
+            _ => Err(format!("unknown topology format '{}'", s)),
+        }
+    }
+}
+
+impl TopologyFormat {
+    /// MIME type of the format.
+    pub(super) fn content_type(self) -> &'static str {
+        match self {
+            TopologyFormat::Dot => "text/vnd.graphviz",
+            TopologyFormat::GraphMl => "application/graphml+xml",
+            TopologyFormat::Json => "application/json",
+        }
+    }
+}
+
+/// Snapshot of the network graph.
+#[derive(Serialize)]
+pub(super) struct Topology {
+    directed: bool,
+    multigraph: bool,
+    graph: Graph,
+    nodes: Vec<TopologyNode>,
+    links: Vec<TopologyLink>,
+}
+
+#[derive(Serialize)]
+struct Graph {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+struct TopologyNode {
+    /// IPv6 address
+    id: String,
+    key: String,
+    version: u16,
+    #[serde(rename = "encodingScheme")]
+    encoding_scheme: Vec<EncodingForm>,
+}
+
+#[derive(Serialize)]
+struct EncodingForm {
+    #[serde(rename = "bitCount")]
+    bit_count: u8,
+    #[serde(rename = "prefixLen")]
+    prefix_len: u8,
+    prefix: String,
+}
+
+/// Link announced by the source node to reach its peer, the target node.
+#[derive(Serialize)]
+struct TopologyLink {
+    source: String,
+    target: String,
+    label: String,
+    #[serde(rename = "peerNum")]
+    peer_num: u16,
+    mtu: u32,
+    value: f64,
+}
+
+impl Topology {
+    /// Take snapshot of all the nodes and their links, sorted by IP address.
+    /// If `bidirectional_only` is set, links without a link in the opposite direction are omitted,
+    /// same as in the routing graph.
+    pub(super) fn snapshot(nodes: &Nodes, bidirectional_only: bool) -> Self {
+        let mut topology = Topology {
+            directed: true,
+            multigraph: true,
+            graph: Graph { name: "cjdns" },
+            nodes: Vec::new(),
+            links: Vec::new(),
+        };
+
+        let mut ips = nodes.all_ips();
+        ips.sort();
+        for ip in ips {
+            let node = match nodes.by_ip(&ip) {
+                Some(node) => node,
+                None => continue,
+            };
+            topology.nodes.push(TopologyNode {
+                id: ip.to_string(),
+                key: node.key.to_string(),
+                version: node.version,
+                encoding_scheme: encoding_forms(&node.encoding_scheme),
+            });
+
+            let links = node.inward_links_by_ip.lock();
+            let mut peers = links.keys().collect::<Vec<_>>();
+            peers.sort();
+            for peer_ip in peers {
+                let peer = match nodes.by_ip(peer_ip) {
+                    Some(peer) => peer,
+                    None => continue,
+                };
+                if bidirectional_only && peer.inward_links_by_ip.lock().get(&ip).is_none() {
+                    continue;
+                }
+                for link in links[peer_ip].iter() {
+                    let link_mut = link.mut_state.lock();
+                    topology.links.push(TopologyLink {
+                        source: ip.to_string(),
+                        target: peer_ip.to_string(),
+                        label: label_string(link.label),
+                        peer_num: link.peer_num,
+                        mtu: link_mut.mtu,
+                        value: link_mut.value,
+                    });
+                }
+            }
+        }
+
+        topology
+    }
+
+    /// Render the graph in the given format.
+    pub(super) fn render(&self, format: TopologyFormat) -> String {
+        match format {
+            TopologyFormat::Dot => self.to_dot(),
+            TopologyFormat::GraphMl => self.to_graphml(),
+            TopologyFormat::Json => serde_json::to_string_pretty(self).expect("internal error: topology isn't serializable"),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let q = dot_quote;
+        let mut out = String::new();
+        writeln!(out, "digraph {} {{", q(self.graph.name)).unwrap();
+        for node in self.nodes.iter() {
+            writeln!(
+                out,
+                "  {} [key={}, version={}, encodingScheme={}];",
+                q(&node.id),
+                q(&node.key),
+                node.version,
+                q(&encoding_scheme_string(&node.encoding_scheme))
+            )
+            .unwrap();
+        }
+        for link in self.links.iter() {
+            writeln!(
+                out,
+                "  {} -> {} [label={}, peerNum={}, mtu={}, value={}];",
+                q(&link.source),
+                q(&link.target),
+                q(&link.label),
+                link.peer_num,
+                link.mtu,
+                link.value
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_graphml(&self) -> String {
+        let e = xml_escape;
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        let keys = [
+            ("node", "key", "string"),
+            ("node", "version", "int"),
+            ("node", "encodingScheme", "string"),
+            ("edge", "label", "string"),
+            ("edge", "peerNum", "int"),
+            ("edge", "mtu", "long"),
+            ("edge", "value", "double"),
+        ];
+        for (domain, name, attr_type) in keys.iter() {
+            writeln!(out, "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"{2}\"/>", name, domain, attr_type).unwrap();
+        }
+        writeln!(out, "  <graph id=\"{}\" edgedefault=\"directed\">", e(self.graph.name)).unwrap();
+        for node in self.nodes.iter() {
+            writeln!(out, "    <node id=\"{}\">", e(&node.id)).unwrap();
+            writeln!(out, "      <data key=\"key\">{}</data>", e(&node.key)).unwrap();
+            writeln!(out, "      <data key=\"version\">{}</data>", node.version).unwrap();
+            writeln!(out, "      <data key=\"encodingScheme\">{}</data>", e(&encoding_scheme_string(&node.encoding_scheme))).unwrap();
+            out.push_str("    </node>\n");
+        }
+        for link in self.links.iter() {
+            writeln!(out, "    <edge source=\"{}\" target=\"{}\">", e(&link.source), e(&link.target)).unwrap();
+            writeln!(out, "      <data key=\"label\">{}</data>", e(&link.label)).unwrap();
+            writeln!(out, "      <data key=\"peerNum\">{}</data>", link.peer_num).unwrap();
+            writeln!(out, "      <data key=\"mtu\">{}</data>", link.mtu).unwrap();
+            writeln!(out, "      <data key=\"value\">{}</data>", link.value).unwrap();
+            out.push_str("    </edge>\n");
+        }
+        out.push_str("  </graph>\n");
+        out.push_str("</graphml>\n");
+        out
+    }
+}
+
+fn encoding_forms(encoding_scheme: &EncodingScheme) -> Vec<EncodingForm> {
+    encoding_scheme
+        .iter()
+        .map(|form| {
+            let (bit_count, prefix_len, prefix) = form.params();
+            EncodingForm {
+                bit_count,
+                prefix_len,
+                prefix: format!("{:x}", prefix),
+            }
+        })
+        .collect()
+}
+
+/// Encoding scheme as a single string attribute: forms as `bitCount/prefixLen/prefix` separated by commas.
+fn encoding_scheme_string(forms: &[EncodingForm]) -> String {
+    forms
+        .iter()
+        .map(|form| format!("{}/{}/{}", form.bit_count, form.prefix_len, form.prefix))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Label in the usual 64-bit form, like `0000.0000.0000.0013`.
+fn label_string(label: RoutingLabel<u32>) -> String {
+    RoutingLabel::<u64>::try_new(label.bits() as u64).expect("internal error: zero label").to_string()
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[test]
+fn test_topology_render() {
+    let topology = Topology {
+        directed: true,
+        multigraph: true,
+        graph: Graph { name: "cjdns" },
+        nodes: vec![
+            TopologyNode {
+                id: "fc00::1".to_string(),
+                key: "key1.k".to_string(),
+                version: 21,
+                encoding_scheme: vec![EncodingForm {
+                    bit_count: 4,
+                    prefix_len: 2,
+                    prefix: "2".to_string(),
+                }],
+            },
+            TopologyNode {
+                id: "fc00::2".to_string(),
+                key: "key2.k".to_string(),
+                version: 20,
+                encoding_scheme: Vec::new(),
+            },
+        ],
+        links: vec![TopologyLink {
+            source: "fc00::1".to_string(),
+            target: "fc00::2".to_string(),
+            label: "0000.0000.0000.0013".to_string(),
+            peer_num: 3,
+            mtu: 1280,
+            value: 0.5,
+        }],
+    };
+
+    let dot = topology.render(TopologyFormat::Dot);
+    assert!(dot.starts_with("digraph \"cjdns\" {\n"));
+    assert!(dot.contains("  \"fc00::1\" [key=\"key1.k\", version=21, encodingScheme=\"4/2/2\"];\n"));
+    assert!(dot.contains("  \"fc00::1\" -> \"fc00::2\" [label=\"0000.0000.0000.0013\", peerNum=3, mtu=1280, value=0.5];\n"));
+
+    let graphml = topology.render(TopologyFormat::GraphMl);
+    assert!(graphml.contains("<key id=\"mtu\" for=\"edge\" attr.name=\"mtu\" attr.type=\"long\"/>"));
+    assert!(graphml.contains("<node id=\"fc00::2\">"));
+    assert!(graphml.contains("<edge source=\"fc00::1\" target=\"fc00::2\">"));
+
+    let json = serde_json::from_str::<serde_json::Value>(&topology.render(TopologyFormat::Json)).expect("bad json");
+    assert_eq!(json["nodes"][0]["id"], "fc00::1");
+    assert_eq!(json["nodes"][0]["encodingScheme"][0]["prefix"], "2");
+    assert_eq!(json["links"][0]["source"], "fc00::1");
+    assert_eq!(json["links"][0]["peerNum"], 3);
+
+    assert_eq!(dot_quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    assert_eq!(xml_escape("<a & 'b'>"), "&lt;a &amp; &apos;b&apos;&gt;");
+    assert_eq!("graphml".parse::<TopologyFormat>(), Ok(TopologyFormat::GraphMl));
+    assert!("svg".parse::<TopologyFormat>().is_err());
+}
@@ -4,27 +4,89 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use warp::filters::BoxedFilter;
 use warp::{Filter, Rejection, Reply};
 
-use crate::server::Server;
+use crate::config::{AuthScope, ListenerConfig, RouteSet};
+use crate::server::{auth, Server};
 
-pub(super) async fn test_srv_task(server: Arc<Server>) {
-    let routes = api(server);
-    warp::serve(routes).run(([127, 0, 0, 1], 3333)).await;
+/// Serve HTTP/WebSocket requests on the listener's address.
+/// Only the route sets enabled for that listener are served.
+pub(super) async fn listener_task(server: Arc<Server>, listener: ListenerConfig) {
+    if let Some(routes) = api(server, &listener.routes) {
+        info!("Listening on {} for {:?}", listener.addr, listener.routes);
+        warp::serve(routes.recover(handlers::handle_rejection)).run(listener.addr).await;
+    } else {
+        warn!("No routes configured for listener {}, ignoring it", listener.addr);
+    }
+}
+
+type BoxedRoutes = BoxedFilter<(Box<dyn Reply>,)>;
+
+fn api(server: Arc<Server>, route_sets: &[RouteSet]) -> Option<BoxedRoutes> {
+    let mut routes = route_sets.iter().map(|&route_set| {
+        let access = authorized(server.clone(), auth::required_scope(route_set));
+        match route_set {
+            RouteSet::Peer => boxed(access.and(peer_api(server.clone()))),
+            RouteSet::Query => boxed(access.and(query_api(server.clone()))),
+            RouteSet::Admin => boxed(access.and(admin_api(server.clone()))),
+        }
+    });
+    let first = routes.next()?;
+    Some(routes.fold(first, |all, r| boxed(all.or(r))))
+}
+
+fn boxed<F, R>(routes: F) -> BoxedRoutes
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply + 'static,
+{
+    routes.map(|reply| Box::new(reply) as Box<dyn Reply>).boxed()
+}
+
+/// Reject the request unless its bearer token grants access to the scope.
+fn authorized(server: Arc<Server>, scope: AuthScope) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .map(move || scope)
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_server(server))
+        .and_then(handlers::check_auth)
+        .untuple_one()
+}
+
+/// Peering routes.
+fn peer_api(server: Arc<Server>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // endpoint '/cjdnsnode_websocket'
+    ws_route(server)
 }
 
-fn api(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+/// Read-only query routes.
+fn query_api(server: Arc<Server>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     // endpoint '/'
     let info = info_route(server.clone());
-    let debug_node = debug_node_route(server.clone());
     let dump = dump_route(server.clone());
     let path = path_route(server.clone());
+    let link_state = link_state_route(server.clone());
     let ni = ni_with_ip_route(server.clone()).or(ni_empty(server.clone()));
     let walk = walk_route(server.clone());
-    // endpoint '/cjdnsnode_websocket'
-    let ws = ws_route(server.clone());
+    let topology = topology_route(server.clone());
+    let analysis = analysis_route(server.clone());
+    let asymmetric_links = asymmetric_links_route(server.clone());
+    let versions = versions_route(server.clone());
+    let config = config_route(server.clone());
+    let metrics = metrics_route(server.clone());
+    // endpoint '/events' (WebSocket)
+    let events = events_route(server.clone());
 
-    info.or(debug_node).or(dump).or(path).or(ni).or(walk).or(ws)
+    info.or(dump).or(path).or(link_state).or(ni).or(walk).or(topology).or(analysis).or(asymmetric_links).or(versions).or(config).or(metrics).or(events)
+}
+
+/// Administrative routes, which change server state.
+fn admin_api(server: Arc<Server>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let debug_node = debug_node_route(server.clone());
+    let config_reload = config_reload_route(server.clone());
+
+    debug_node.or(config_reload)
 }
 
 fn info_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
@@ -32,10 +94,40 @@ fn info_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error =
 }
 
 fn debug_node_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::path::path("debugnode")
+    let debug_node = warp::path::path("debugnode");
+    let list = debug_node
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_server(server.clone()))
+        .and_then(handlers::handle_debug_node_list);
+    let log = debug_node
+        .and(warp::path::param())
+        .and(warp::path::path("log"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_server(server.clone()))
+        .and_then(handlers::handle_debug_node_log);
+    let clear_log = debug_node
+        .and(warp::path::param())
+        .and(warp::path::path("log"))
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_server(server.clone()))
+        .and_then(handlers::handle_debug_node_clear_log);
+    let stop = debug_node
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(with_server(server.clone()))
+        .and_then(handlers::handle_debug_node_stop);
+    let start = debug_node
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::query::<handlers::DebugNodeQuery>())
         .and(with_server(server))
-        .and_then(handlers::handle_debug_node)
+        .and_then(handlers::handle_debug_node);
+
+    list.or(log).or(clear_log).or(stop).or(start)
 }
 
 fn dump_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
@@ -50,8 +142,21 @@ fn path_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error =
     warp::path::path("path")
         .and(warp::path::param())
         .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::query::<handlers::PathQuery>())
         .and(with_server(server))
         .and_then(handlers::handle_path)
+        .recover(handlers::handle_path_rejection)
+}
+
+fn link_state_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("linkstate")
+        .and(warp::path::param())
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::query::<handlers::LinkStateQuery>())
+        .and(with_server(server))
+        .and_then(handlers::handle_link_state)
 }
 
 fn ni_with_ip_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
@@ -72,7 +177,73 @@ fn walk_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error =
     warp::path::path("walk").and(with_server(server)).and_then(handlers::handle_walk)
 }
 
-fn ws_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+fn topology_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("topology")
+        .and(warp::path::end())
+        .and(warp::query::<handlers::TopologyQuery>())
+        .and(with_server(server))
+        .and_then(handlers::handle_topology)
+}
+
+fn analysis_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("analysis")
+        .and(warp::path::end())
+        .and(with_server(server))
+        .and_then(handlers::handle_analysis)
+}
+
+fn asymmetric_links_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("links")
+        .and(warp::path::path("asymmetric"))
+        .and(warp::path::end())
+        .and(with_server(server))
+        .and_then(handlers::handle_asymmetric_links)
+}
+
+fn versions_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("versions")
+        .and(warp::path::end())
+        .and(warp::query::<handlers::VersionsQuery>())
+        .and(with_server(server))
+        .and_then(handlers::handle_versions)
+}
+
+fn metrics_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let metrics_header = warp::reply::with::header("content-type", "text/plain; version=0.0.4");
+    warp::path::path("metrics")
+        .and(warp::path::end())
+        .and(with_server(server))
+        .and_then(handlers::handle_metrics)
+        .with(metrics_header)
+}
+
+fn events_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("events")
+        .and(warp::path::end())
+        .and(warp::query::<handlers::EventsQuery>())
+        .and(with_server(server))
+        .and(warp::ws())
+        .and_then(handlers::handle_events)
+}
+
+fn config_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("config")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_server(server))
+        .and_then(handlers::handle_config)
+}
+
+fn config_reload_route(server: Arc<Server>) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path::path("config")
+        .and(warp::path::path("reload"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_server(server))
+        .and_then(handlers::handle_config_reload)
+}
+
+fn ws_route(server: Arc<Server>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     warp::path::path("cjdnsnode_websocket")
         .and(warp::addr::remote())
         .and(with_server(server))
@@ -94,35 +265,73 @@ fn with_server(server: Arc<Server>) -> impl Filter<Extract = (Arc<Server>,), Err
 }
 
 mod handlers {
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, HashSet};
     use std::convert::{Infallible, TryFrom};
     use std::sync::Arc;
+    use std::time::Duration;
 
+    use futures::{SinkExt, StreamExt};
+    use serde::Deserialize;
     use serde_json::json;
     use serde_json::Value as JsonValue;
     use thiserror::Error;
+    use tokio::select;
+    use tokio::sync::broadcast::RecvError;
     use warp::reject::Reject;
+    use warp::ws::{Message, WebSocket, Ws};
     use warp::{http::StatusCode, Rejection, Reply};
 
     use cjdns_ann::{Announcement, Entity};
     use cjdns_core::{EncodingScheme, RoutingLabel};
     use cjdns_keys::CJDNS_IP6;
 
-    use crate::server::{route::get_route, Server};
+    use crate::config::{AuthScope, CostModel};
+    use crate::server::analysis::UndirectedGraph;
+    use crate::server::debug_trace::DebugTrace;
+    use crate::server::events::TopologyEvent;
+    use crate::server::link::Link;
+    use crate::server::route::{get_routes, node_graph_peers, routes_through, Route, RouteConstraints, RoutingError};
+    use crate::server::topology::{Topology, TopologyFormat};
+    use crate::server::{auth, metrics, Server};
+    use crate::utils::ip6_prefix::Ip6Prefix;
     use crate::utils::timestamp::make_timestamp;
 
     use super::node_info::nodes_info;
 
-    use self::warp_pretty_print_json_reply::reply_json;
+    use self::warp_pretty_print_json_reply::{reply_json, Json};
 
     #[derive(Error, Debug)]
     enum WebServerError {
         #[error("Bad IPv6 address '{0}': {1}")]
         BadIP6Address(String, String),
+
+        #[error("{0}")]
+        BadIP6Prefix(String),
+
+        #[error("unauthorized")]
+        Unauthorized,
     }
 
     impl Reject for WebServerError {}
 
+    pub(super) async fn check_auth(scope: AuthScope, auth: Option<String>, server: Arc<Server>) -> Result<(), Rejection> {
+        if auth::is_authorized(&server.config.read(), scope, auth.as_deref()) {
+            Ok(())
+        } else {
+            Err(warp::reject::custom(WebServerError::Unauthorized))
+        }
+    }
+
+    /// Reply to requests rejected for the lack of access with 401, leave other rejections to warp.
+    pub(super) async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+        if let Some(err @ WebServerError::Unauthorized) = rejection.find::<WebServerError>() {
+            let reply = json! {{ "error": err.to_string() }};
+            let reply = warp::reply::with_status(reply_json(&reply), StatusCode::UNAUTHORIZED);
+            return Ok(warp::reply::with_header(reply, "www-authenticate", "Bearer"));
+        }
+        Err(rejection)
+    }
+
     pub(super) async fn handle_info(server: Arc<Server>) -> Result<impl Reply, Infallible> {
         let peers_info = server.peers.get_info();
         let nodes_count = server.nodes.count();
@@ -146,31 +355,371 @@ mod handlers {
         Ok(reply_json(&reply))
     }
 
-    pub(super) async fn handle_debug_node(ip6: String, server: Arc<Server>) -> Result<StatusCode, Rejection> {
+    #[derive(Deserialize)]
+    pub(super) struct DebugNodeQuery {
+        /// How long to trace the node, in seconds (an hour if not set, a week at most)
+        duration: Option<u64>,
+    }
+
+    /// Start tracing the node, or change the expiry of its trace.
+    pub(super) async fn handle_debug_node(ip6: String, query: DebugNodeQuery, server: Arc<Server>) -> Result<warp::reply::Response, Rejection> {
+        const DEFAULT_DURATION: Duration = Duration::from_secs(60 * 60);
+        const MAX_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+        let ip = CJDNS_IP6::try_from(ip6.as_str()).map_err(|e| warp::reject::custom(WebServerError::BadIP6Address(ip6, e.to_string())))?;
+        let duration = query.duration.map(Duration::from_secs).unwrap_or(DEFAULT_DURATION);
+        let expires_at = Some(duration).filter(|&d| d <= MAX_DURATION).and_then(|d| server.clock.instant().checked_add(d));
+        match expires_at {
+            Some(expires_at) => {
+                server.debug_trace.start(ip, expires_at);
+                Ok(StatusCode::OK.into_response())
+            }
+            None => {
+                let error = format!("duration must not exceed {} seconds", MAX_DURATION.as_secs());
+                error_reply(StatusCode::BAD_REQUEST, error).map(Reply::into_response).map_err(|e| match e {})
+            }
+        }
+    }
+
+    pub(super) async fn handle_debug_node_list(server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let traces = server.debug_trace.list(server.clock.instant()).into_iter().map(|trace| {
+            json! {{
+                "node": trace.node.to_string(),
+                "active": trace.expires_in.is_some(),
+                "expiresInSeconds": trace.expires_in.map(|d| d.as_secs()),
+                "lines": trace.lines,
+                "droppedLines": trace.dropped,
+            }}
+        });
+        let reply = json! {{
+            "bufferLines": DebugTrace::BUFFER_LINES,
+            "nodes": traces.collect::<Vec<_>>(),
+        }};
+        Ok(reply_json(&reply))
+    }
+
+    pub(super) async fn handle_debug_node_log(ip6: String, server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let ip = match CJDNS_IP6::try_from(ip6.as_str()) {
+            Ok(ip) => ip,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, WebServerError::BadIP6Address(ip6, e.to_string()).to_string()),
+        };
+        let lines = match server.debug_trace.lines(&ip) {
+            Some(lines) => lines,
+            None => return error_reply(StatusCode::NOT_FOUND, format!("node {} is not traced", ip)),
+        };
+        let reply = json! {{
+            "node": ip.to_string(),
+            "active": server.debug_trace.is_traced(&ip, server.clock.instant()),
+            "lines": lines,
+        }};
+        Ok(warp::reply::with_status(reply_json(&reply), StatusCode::OK))
+    }
+
+    pub(super) async fn handle_debug_node_clear_log(ip6: String, server: Arc<Server>) -> Result<StatusCode, Rejection> {
         let ip = CJDNS_IP6::try_from(ip6.as_str()).map_err(|e| warp::reject::custom(WebServerError::BadIP6Address(ip6, e.to_string())))?;
-        server.mut_state.lock().debug_node = Some(ip);
-        return Ok(StatusCode::OK);
+        if server.debug_trace.clear(&ip, server.clock.instant()) {
+            Ok(StatusCode::OK)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    }
+
+    /// Stop tracing the node and drop its trace buffer.
+    pub(super) async fn handle_debug_node_stop(ip6: String, server: Arc<Server>) -> Result<StatusCode, Rejection> {
+        let ip = CJDNS_IP6::try_from(ip6.as_str()).map_err(|e| warp::reject::custom(WebServerError::BadIP6Address(ip6, e.to_string())))?;
+        if server.debug_trace.stop(&ip) {
+            Ok(StatusCode::OK)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    }
+
+    pub(super) async fn handle_metrics(server: Arc<Server>) -> Result<String, Infallible> {
+        Ok(metrics::render(&server))
+    }
+
+    #[derive(Deserialize)]
+    pub(super) struct EventsQuery {
+        /// Comma-separated list of IPv6 prefixes to filter events by
+        prefix: Option<String>,
+    }
+
+    pub(super) async fn handle_events(query: EventsQuery, server: Arc<Server>, ws: Ws) -> Result<impl Reply, Rejection> {
+        let prefixes = query
+            .prefix
+            .as_ref()
+            .map(|s| s.split(',').map(str::parse::<Ip6Prefix>).collect::<Result<Vec<_>, _>>())
+            .transpose()
+            .map_err(|e| warp::reject::custom(WebServerError::BadIP6Prefix(e)))?
+            .unwrap_or_default();
+        Ok(ws.on_upgrade(move |ws_conn| events_feed(ws_conn, server, prefixes)))
+    }
+
+    /// Send topology change events to the WebSocket client until it disconnects.
+    async fn events_feed(ws_conn: WebSocket, server: Arc<Server>, prefixes: Vec<Ip6Prefix>) {
+        enum Input {
+            Event(Result<TopologyEvent, RecvError>),
+            WsMessage(Option<Result<Message, warp::Error>>),
+        }
+
+        let mut events = server.events.subscribe();
+        let (mut ws_write, mut ws_read) = ws_conn.split();
+
+        loop {
+            let input = select! {
+                event = events.recv() => Input::Event(event),
+                message = ws_read.next() => Input::WsMessage(message),
+            };
+            match input {
+                Input::Event(Ok(event)) => {
+                    if !event.matches(&prefixes) {
+                        continue;
+                    }
+                    let json = serde_json::to_string(&event).expect("internal error: event isn't serializable");
+                    if ws_write.send(Message::text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Input::Event(Err(RecvError::Lagged(count))) => {
+                    warn!("Events subscriber is too slow, {} events dropped", count);
+                }
+                Input::Event(Err(RecvError::Closed)) => break,
+                Input::WsMessage(Some(Ok(message))) if !message.is_close() => { /* Ignore incoming messages */ }
+                Input::WsMessage(_) => break,
+            }
+        }
+    }
+
+    pub(super) async fn handle_config(server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let config = server.config.read().clone();
+        Ok(reply_json(&config))
+    }
+
+    pub(super) async fn handle_config_reload(server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        match server.reload_config().await {
+            Ok(config) => Ok(warp::reply::with_status(reply_json(&config), StatusCode::OK)),
+            Err(err) => {
+                let reply = json! {{ "error": err.to_string() }};
+                Ok(warp::reply::with_status(reply_json(&reply), StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
     }
 
     pub(super) async fn handle_dump(server: Arc<Server>) -> Result<Vec<u8>, Infallible> {
         Ok(server.nodes.anns_dump())
     }
 
-    pub(super) async fn handle_path(src: String, tar: String, server: Arc<Server>) -> Result<impl Reply, Rejection> {
-        let src_ip = CJDNS_IP6::try_from(src.as_str()).map_err(|e| warp::reject::custom(WebServerError::BadIP6Address(src, e.to_string())))?;
-        let tar_ip = CJDNS_IP6::try_from(tar.as_str()).map_err(|e| warp::reject::custom(WebServerError::BadIP6Address(tar, e.to_string())))?;
+    #[derive(Deserialize)]
+    pub(super) struct PathQuery {
+        /// Maximum number of alternative routes to return (configured `maxRoutes` if not set)
+        max: Option<usize>,
+
+        /// Comma-separated list of IPv6 addresses of the nodes the route must not go through
+        avoid: Option<String>,
+
+        /// Minimum MTU of every link in the route
+        mtu: Option<u32>,
+
+        /// Maximum number of hops in the route
+        hops: Option<usize>,
+
+        /// Link cost model (configured `costModel` if not set)
+        cost: Option<String>,
+    }
+
+    /// JSON reply with the error message.
+    fn error_reply(status: StatusCode, error: String) -> Result<warp::reply::WithStatus<Json>, Infallible> {
+        let reply = json! {{ "error": error }};
+        Ok(warp::reply::with_status(reply_json(&reply), status))
+    }
+
+    /// Reply with JSON to the route queries with a malformed query string, leave other rejections to warp.
+    pub(super) async fn handle_path_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+        if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+            let reply = json! {{ "error": err.to_string() }};
+            return Ok(warp::reply::with_status(reply_json(&reply), StatusCode::BAD_REQUEST));
+        }
+        Err(rejection)
+    }
+
+    pub(super) async fn handle_path(src: String, tar: String, query: PathQuery, server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let src_ip = match CJDNS_IP6::try_from(src.as_str()) {
+            Ok(ip) => ip,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, WebServerError::BadIP6Address(src, e.to_string()).to_string()),
+        };
+        let tar_ip = match CJDNS_IP6::try_from(tar.as_str()) {
+            Ok(ip) => ip,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, WebServerError::BadIP6Address(tar, e.to_string()).to_string()),
+        };
+        let avoid = query
+            .avoid
+            .as_ref()
+            .map(|s| {
+                s.split(',')
+                    .map(|ip| CJDNS_IP6::try_from(ip).map_err(|e| WebServerError::BadIP6Address(ip.to_string(), e.to_string())))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose();
+        let avoid = match avoid {
+            Ok(avoid) => avoid.unwrap_or_default(),
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        let constraints = RouteConstraints {
+            avoid,
+            min_mtu: query.mtu,
+            max_hops: query.hops,
+        };
+
         let src = server.nodes.by_ip(&src_ip);
         let tar = server.nodes.by_ip(&tar_ip);
-        warn!("http getRoute req {} {}", src_ip, tar_ip);
+        debug!("http getRoute req {} {}", src_ip, tar_ip);
         if src.is_none() {
-            return Ok("src not found".to_string());
+            return error_reply(StatusCode::NOT_FOUND, format!("src node {} not found", src_ip));
         }
         if tar.is_none() {
-            return Ok("tar not found".to_string());
+            return error_reply(StatusCode::NOT_FOUND, format!("tar node {} not found", tar_ip));
+        }
+
+        const MAX_ROUTES_LIMIT: usize = 16;
+        let max_routes = query.max.unwrap_or_else(|| server.config.read().max_routes).min(MAX_ROUTES_LIMIT);
+        let cost_model = match query.cost.as_ref().map(|s| s.parse::<CostModel>()).transpose() {
+            Ok(cost_model) => cost_model.unwrap_or_else(|| server.config.read().cost_model),
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, e),
+        };
+        match get_routes(server.clone(), src, tar, max_routes, &constraints, cost_model) {
+            Ok(routes) => {
+                // The best route first
+                let reply = json! {{
+                    "src": src_ip.to_string(),
+                    "tar": tar_ip.to_string(),
+                    "costModel": cost_model,
+                    "routes": routes.iter().map(|route| json_route(&server, route)).collect::<Vec<_>>(),
+                }};
+                Ok(warp::reply::with_status(reply_json(&reply), StatusCode::OK))
+            }
+            Err(e @ RoutingError::RouteNotFound(..)) => error_reply(StatusCode::NOT_FOUND, e.to_string()),
+            Err(e @ RoutingError::NoInput) => error_reply(StatusCode::BAD_REQUEST, e.to_string()),
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub(super) struct LinkStateQuery {
+        /// Start of the time range, Unix time in seconds (from the earliest sample if not set)
+        from: Option<u64>,
+
+        /// End of the time range, Unix time in seconds (up to the latest sample if not set)
+        to: Option<u64>,
+
+        /// Interval to average the samples over, seconds (no downsampling if not set)
+        step: Option<u64>,
+    }
+
+    /// Link state history of the links announced by the node as its inward links from the peer.
+    pub(super) async fn handle_link_state(node: String, peer: String, query: LinkStateQuery, server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let node_ip = match CJDNS_IP6::try_from(node.as_str()) {
+            Ok(ip) => ip,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, WebServerError::BadIP6Address(node, e.to_string()).to_string()),
+        };
+        let peer_ip = match CJDNS_IP6::try_from(peer.as_str()) {
+            Ok(ip) => ip,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, WebServerError::BadIP6Address(peer, e.to_string()).to_string()),
+        };
+        let first_slot = query.from.unwrap_or(0) / Link::TIMESLOT_SECONDS;
+        let last_slot = query.to.map(|to| to / Link::TIMESLOT_SECONDS).unwrap_or(u64::MAX);
+        if first_slot > last_slot {
+            return error_reply(StatusCode::BAD_REQUEST, "time range start is after its end".to_string());
         }
-        match get_route(server.clone(), src, tar) {
-            Ok(r) => Ok(r.label.to_string()),
-            Err(e) => Ok(format!("{:?}", e)),
+        let step = (query.step.unwrap_or(0) / Link::TIMESLOT_SECONDS).max(1);
+
+        let node = match server.nodes.by_ip(&node_ip) {
+            Some(node) => node,
+            None => return error_reply(StatusCode::NOT_FOUND, format!("node {} not found", node_ip)),
+        };
+        let links = match node.inward_links_by_ip.lock().get(&peer_ip) {
+            Some(links) => links.clone(),
+            None => return error_reply(StatusCode::NOT_FOUND, format!("no links to {} from {}", node_ip, peer_ip)),
+        };
+
+        let links = links.iter().map(|link| {
+            let series = link.history(first_slot, last_slot, step).into_iter().map(|point| {
+                json! {{
+                    "time": point.slot * Link::TIMESLOT_SECONDS,
+                    "samples": point.samples,
+                    "drops": point.drops,
+                    "lag": point.lag,
+                    "kbRecv": point.kb_recv,
+                    "value": point.value,
+                }}
+            });
+            json! {{
+                "label": json_label(Some(link.label)),
+                "peerNum": link.peer_num,
+                "series": series.collect::<Vec<_>>(),
+            }}
+        });
+        let reply = json! {{
+            "node": node_ip.to_string(),
+            "peer": peer_ip.to_string(),
+            "step": step * Link::TIMESLOT_SECONDS,
+            "links": links.collect::<Vec<_>>(),
+        }};
+        Ok(warp::reply::with_status(reply_json(&reply), StatusCode::OK))
+    }
+
+    /// Route with the explanation of every hop.
+    fn json_route(server: &Server, route: &Route) -> JsonValue {
+        let hops = route.path.windows(2).zip(route.hops.iter()).map(|(pair, hop)| {
+            let (from_ip, to_ip) = (&pair[0], &pair[1]);
+            let to_node = server.nodes.by_ip(to_ip);
+            // The link used is the one announced by the next node as its inward link from the previous one
+            let link = to_node
+                .as_ref()
+                .and_then(|node| node.inward_links_by_ip.lock().get(from_ip).and_then(|links| links.first().cloned()));
+            let link_json = link.map(|link| {
+                let link_state = link.mut_state.lock().clone();
+                let latest_sample = link.link_state.lock().iter().max_by_key(|(slot, _)| **slot).map(|(&slot, state)| {
+                    json! {{
+                        "slot": slot,
+                        "drops": state.drops,
+                        "lag": state.lag,
+                        "kbRecv": state.kb_recv,
+                    }}
+                });
+                json! {{
+                    "peerNum": link.peer_num,
+                    "encodingFormNum": link.encoding_form_number,
+                    "value": link_state.value,
+                    "mtu": link_state.mtu,
+                    "latestLinkState": latest_sample,
+                }}
+            });
+            json! {{
+                "from": from_ip.to_string(),
+                "node": json_route_node(server, to_ip),
+                "label": hop.label.to_string(),
+                "origLabel": json_label(Some(hop.orig_label)),
+                "reEncoded": hop.label.bits() != hop.orig_label.bits() as u64,
+                "inverseFormNum": hop.inverse_form_num,
+                "encodingScheme": json_encoding_scheme(&hop.scheme),
+                "link": link_json,
+            }}
+        });
+        json! {{
+            "label": route.label.to_string(),
+            "src": route.path.first().map(|ip| json_route_node(server, ip)),
+            "hops": hops.collect::<Vec<_>>(),
+        }}
+    }
+
+    fn json_route_node(server: &Server, ip: &CJDNS_IP6) -> JsonValue {
+        match server.nodes.by_ip(ip) {
+            Some(node) => json! {{
+                "ipv6": node.ipv6.to_string(),
+                "key": node.key.to_string(),
+                "version": node.version,
+            }},
+            None => json! {{
+                "ipv6": ip.to_string(),
+            }},
         }
     }
 
@@ -300,6 +849,139 @@ mod handlers {
         Ok(out)
     }
 
+    #[derive(Deserialize)]
+    pub(super) struct TopologyQuery {
+        /// Graph format: `dot`, `graphml` or `json` (default)
+        format: Option<String>,
+
+        /// Include only links which have a link in the opposite direction, as used for routing
+        bidirectional: Option<bool>,
+    }
+
+    pub(super) async fn handle_topology(query: TopologyQuery, server: Arc<Server>) -> Result<warp::reply::Response, Infallible> {
+        let format = match query.format.as_deref().unwrap_or("json").parse::<TopologyFormat>() {
+            Ok(format) => format,
+            Err(e) => return error_reply(StatusCode::BAD_REQUEST, e).map(Reply::into_response),
+        };
+        let topology = Topology::snapshot(&server.nodes, query.bidirectional.unwrap_or(false));
+        let reply = warp::reply::with_header(topology.render(format), "content-type", format.content_type());
+        Ok(reply.into_response())
+    }
+
+    /// Health metrics of the routing graph, to find single points of failure.
+    pub(super) async fn handle_analysis(server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let mut graph = UndirectedGraph::new();
+        for (ip, peers) in node_graph_peers(&server) {
+            graph.add_node(ip.clone());
+            for peer in peers {
+                graph.add_link(ip.clone(), peer);
+            }
+        }
+
+        let components = graph.components();
+        let (articulation_points, bridges) = graph.cut_points();
+        let self_ip = server.mut_state.lock().self_node.as_ref().map(|node| node.ipv6.clone());
+        let reachability = self_ip.as_ref().map(|self_ip| {
+            let reachable = graph.reachable_from(self_ip);
+            let unreachable = components.iter().flatten().filter(|&ip| reachable.binary_search(ip).is_err());
+            json! {{
+                "reachable": reachable.len(),
+                "unreachable": unreachable.map(|ip| ip.to_string()).collect::<Vec<_>>(),
+            }}
+        });
+
+        let reply = json! {{
+            "nodes": graph.node_count(),
+            "links": graph.link_count(),
+            "components": json!{{
+                "count": components.len(),
+                "sizes": components.iter().map(Vec::len).collect::<Vec<_>>(),
+            }},
+            "articulationPoints": articulation_points.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+            "bridges": bridges.iter().map(|(a, b)| vec![a.to_string(), b.to_string()]).collect::<Vec<_>>(),
+            "degreeDistribution": graph.degree_distribution(),
+            "approxDiameter": graph.approx_diameter(),
+            "selfNode": self_ip.map(|ip| ip.to_string()),
+            "reachableFromSelf": reachability,
+        }};
+
+        Ok(reply_json(&reply))
+    }
+
+    /// Links announced by a node, while its peer doesn't announce the link back, the longest lasting first.
+    pub(super) async fn handle_asymmetric_links(server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        let links = server.asymmetric_links.update(&server.nodes, server.clock.instant());
+        let reply = json! {{
+            "total": links.len(),
+            "links": links.iter().map(|link| {
+                json!{{
+                    "node": link.node.to_string(),
+                    "peer": link.peer.to_string(),
+                    "peerKnown": link.peer_known,
+                    "missing": json!{{
+                        "announcer": link.peer.to_string(),
+                        "from": link.node.to_string(),
+                    }},
+                    "durationSeconds": link.duration.as_secs(),
+                }}
+            }).collect::<Vec<_>>(),
+        }};
+        Ok(reply_json(&reply))
+    }
+
+    #[derive(Deserialize)]
+    pub(super) struct VersionsQuery {
+        /// Minimum protocol version (configured `minNodeVersion` if not set)
+        min: Option<u16>,
+    }
+
+    /// Protocol versions of the known nodes, the newest first, and the impact of outdated ones on routing:
+    /// how many of the best routes from a sample of nodes to all the others go through outdated nodes.
+    pub(super) async fn handle_versions(query: VersionsQuery, server: Arc<Server>) -> Result<impl Reply, Infallible> {
+        // Searching from every node of a large network would take too long for a web request
+        const ROUTE_SAMPLE_SOURCES: usize = 64;
+
+        let mut by_version = BTreeMap::<u16, Vec<CJDNS_IP6>>::new();
+        for ip in server.nodes.all_ips() {
+            if let Some(node) = server.nodes.by_ip(&ip) {
+                by_version.entry(node.version).or_default().push(ip);
+            }
+        }
+        let total_nodes = by_version.values().map(Vec::len).sum::<usize>();
+
+        let min_version = query.min.or_else(|| server.config.read().min_node_version);
+        let outdated = match min_version {
+            Some(min_version) => {
+                let outdated = by_version.range(..min_version).flat_map(|(_, ips)| ips.iter().cloned()).collect::<HashSet<_>>();
+                let outdated_count = outdated.len();
+                let routes = routes_through(&server, outdated, ROUTE_SAMPLE_SOURCES).await;
+                Some(json! {{
+                    "nodes": outdated_count,
+                    "routeSources": routes.sources,
+                    "routes": routes.routes,
+                    "routesThroughOutdated": routes.through,
+                }})
+            }
+            None => None,
+        };
+
+        let versions = by_version.iter_mut().rev().map(|(version, ips)| {
+            ips.sort();
+            json! {{
+                "version": version,
+                "count": ips.len(),
+                "nodes": ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+            }}
+        });
+        let reply = json! {{
+            "totalNodes": total_nodes,
+            "versions": versions.collect::<Vec<_>>(),
+            "minVersion": min_version,
+            "outdated": outdated,
+        }};
+        Ok(reply_json(&reply))
+    }
+
     fn json_encoding_scheme(encoding_scheme: &EncodingScheme) -> JsonValue {
         json!(encoding_scheme
             .iter()
@@ -465,3 +1147,87 @@ mod node_info {
         NodesInfo { nodes, total_ann, resets }
     }
 }
+
+#[tokio::test]
+async fn test_versions_route() {
+    use std::time::SystemTime;
+
+    use crate::server::tests::{add_numbered_node, link_nodes, test_server};
+    use crate::utils::clock::ManualClock;
+
+    // Chain of nodes 1-2-3, each one a version newer than the previous one
+    let server = Arc::new(test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH))));
+    let nodes = (1..=3).map(|n| add_numbered_node(&server, n, 19 + n)).collect::<Vec<_>>();
+    link_nodes(&server, &nodes[0], &nodes[1]);
+    link_nodes(&server, &nodes[1], &nodes[2]);
+
+    let res = warp::test::request().path("/versions?min=21").reply(&versions_route(server.clone())).await;
+    assert_eq!(res.status(), 200);
+    let reply = serde_json::from_slice::<serde_json::Value>(res.body()).expect("bad json");
+    assert_eq!(reply["totalNodes"], 3);
+    let versions = reply["versions"].as_array().expect("versions");
+    assert_eq!(versions.iter().map(|v| v["version"].as_u64().unwrap_or(0)).collect::<Vec<_>>(), vec![22, 21, 20]);
+    assert_eq!(versions[2]["count"], 1);
+    assert_eq!(versions[2]["nodes"][0], nodes[0].ipv6.to_string());
+
+    // Routes both ways between all the nodes, those to and from node 1 are through an outdated node
+    assert_eq!(reply["minVersion"], 21);
+    assert_eq!(reply["outdated"]["nodes"], 1);
+    assert_eq!(reply["outdated"]["routeSources"], 3);
+    assert_eq!(reply["outdated"]["routes"], 6);
+    assert_eq!(reply["outdated"]["routesThroughOutdated"], 4);
+
+    let res = warp::test::request().path("/versions").reply(&versions_route(server)).await;
+    let reply = serde_json::from_slice::<serde_json::Value>(res.body()).expect("bad json");
+    assert!(reply["outdated"].is_null(), "no minimum version configured");
+}
+
+#[tokio::test]
+async fn test_path_route_errors() {
+    use std::time::SystemTime;
+
+    use crate::server::tests::test_server;
+    use crate::utils::clock::ManualClock;
+
+    let server = Arc::new(test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH))));
+    let ip = "fc00:0000:0000:0000:0000:0000:0000:0001";
+
+    let res = warp::test::request().path(&format!("/path/{}/{}?max=many", ip, ip)).reply(&path_route(server.clone())).await;
+    assert_eq!(res.status(), 400);
+    let reply = serde_json::from_slice::<serde_json::Value>(res.body()).expect("bad json");
+    assert_eq!(reply["error"], "Invalid query string");
+
+    let res = warp::test::request().path(&format!("/path/{}/bad", ip)).reply(&path_route(server.clone())).await;
+    assert_eq!(res.status(), 400);
+    let reply = serde_json::from_slice::<serde_json::Value>(res.body()).expect("bad json");
+    assert!(reply["error"].as_str().expect("error").starts_with("Bad IPv6 address 'bad'"));
+
+    // Other routes aren't affected
+    let res = warp::test::request().path("/other").reply(&path_route(server)).await;
+    assert_eq!(res.status(), 404);
+}
+
+#[tokio::test]
+async fn test_debug_node_duration() {
+    use std::convert::TryFrom;
+    use std::time::SystemTime;
+
+    use cjdns_keys::CJDNS_IP6;
+
+    use crate::server::tests::test_server;
+    use crate::utils::clock::ManualClock;
+
+    let server = Arc::new(test_server(Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH))));
+    let ip = CJDNS_IP6::try_from("fc00:0000:0000:0000:0000:0000:0000:0001").expect("bad ip");
+    let path = |duration: u64| format!("/debugnode/{}?duration={}", ip, duration);
+
+    let res = warp::test::request().method("PUT").path(&path(u64::MAX)).reply(&debug_node_route(server.clone())).await;
+    assert_eq!(res.status(), 400);
+    let reply = serde_json::from_slice::<serde_json::Value>(res.body()).expect("bad json");
+    assert_eq!(reply["error"], "duration must not exceed 604800 seconds");
+    assert!(!server.debug_trace.is_traced(&ip, server.clock.instant()));
+
+    let res = warp::test::request().method("PUT").path(&path(60)).reply(&debug_node_route(server.clone())).await;
+    assert_eq!(res.status(), 200);
+    assert!(server.debug_trace.is_traced(&ip, server.clock.instant()));
+}
@@ -1,5 +1,8 @@
+pub mod clock;
+pub mod ip6_prefix;
 pub mod node;
 pub mod rand;
 pub mod seq;
+pub mod shutdown;
 pub mod task;
 pub mod timestamp;
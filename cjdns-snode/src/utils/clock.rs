@@ -0,0 +1,91 @@
+//! Time source abstraction, so that time-dependent logic can run on a simulated clock
+
+use std::time::{Duration, Instant, SystemTime};
+
+use parking_lot::Mutex;
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    /// Current wall clock time, used for comparing with announcement timestamps.
+    fn now(&self) -> SystemTime;
+
+    /// Current monotonic time, used for measuring intervals.
+    fn instant(&self) -> Instant;
+}
+
+/// Real system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock which only moves when told to, for tests and offline replays.
+/// Both the wall clock and the monotonic time move together.
+pub struct ManualClock {
+    start_time: SystemTime,
+    start_instant: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// New clock showing the given wall clock time.
+    pub fn new(start_time: SystemTime) -> Self {
+        ManualClock {
+            start_time,
+            start_instant: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    /// Move the clock forward.
+    #[cfg(test)]
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock() += by;
+    }
+
+    /// Move the clock forward to the given time. Never moves the clock backward.
+    pub fn advance_to(&self, time: SystemTime) {
+        if let Ok(elapsed) = time.duration_since(self.start_time) {
+            let mut current = self.elapsed.lock();
+            *current = elapsed.max(*current);
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.start_time + *self.elapsed.lock()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start_instant + *self.elapsed.lock()
+    }
+}
+
+#[test]
+fn test_manual_clock() {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let clock = ManualClock::new(start);
+    let t0 = clock.instant();
+    assert_eq!(clock.now(), start);
+
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(clock.now(), start + Duration::from_secs(30));
+    assert_eq!(clock.instant() - t0, Duration::from_secs(30));
+
+    clock.advance_to(start + Duration::from_secs(100));
+    assert_eq!(clock.now(), start + Duration::from_secs(100));
+    assert_eq!(clock.instant() - t0, Duration::from_secs(100));
+
+    // Never goes back
+    clock.advance_to(start + Duration::from_secs(50));
+    clock.advance_to(start - Duration::from_secs(50));
+    assert_eq!(clock.now(), start + Duration::from_secs(100));
+}
@@ -0,0 +1,90 @@
+//! IPv6 address prefix
+
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use cjdns_keys::CJDNS_IP6;
+
+/// IPv6 address prefix in CIDR notation, like `fc00::/8`.
+/// Address without prefix length matches that single address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ip6Prefix {
+    addr: [u8; 16],
+    len: u8,
+}
+
+impl Ip6Prefix {
+    /// Check whether the address starts with this prefix.
+    pub fn matches(&self, ip: &CJDNS_IP6) -> bool {
+        let ip = ip.raw();
+        let full_bytes = (self.len / 8) as usize;
+        if ip[..full_bytes] != self.addr[..full_bytes] {
+            return false;
+        }
+        let rem_bits = self.len % 8;
+        if rem_bits == 0 {
+            return true;
+        }
+        let mask = 0xFF_u8 << (8 - rem_bits);
+        ip[full_bytes] & mask == self.addr[full_bytes] & mask
+    }
+}
+
+impl FromStr for Ip6Prefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.find('/') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => (s, "128"),
+        };
+        let addr = Ipv6Addr::from_str(addr).map_err(|e| format!("bad IPv6 prefix '{}': {}", s, e))?;
+        let len = u8::from_str(len).map_err(|e| format!("bad IPv6 prefix length in '{}': {}", s, e))?;
+        if len > 128 {
+            return Err(format!("bad IPv6 prefix length in '{}': must be at most 128", s));
+        }
+        Ok(Ip6Prefix { addr: addr.octets(), len })
+    }
+}
+
+impl fmt::Display for Ip6Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv6Addr::from(self.addr), self.len)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ip6Prefix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Ip6Prefix::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Ip6Prefix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[test]
+fn test_ip6_prefix() {
+    use std::convert::TryFrom;
+
+    let ip = CJDNS_IP6::try_from("fc50:71b5:aebf:7b70:6577:0ec8:2542:9dd9").expect("bad test ip");
+    let matches = |prefix: &str| Ip6Prefix::from_str(prefix).expect("bad test prefix").matches(&ip);
+    assert!(matches("fc00::/8"));
+    assert!(matches("fc50::/16"));
+    assert!(matches("fc50:7000::/20"));
+    assert!(!matches("fc50:8000::/17"));
+    assert!(!matches("fc51::/16"));
+    assert!(matches("::/0"));
+    assert!(matches("fc50:71b5:aebf:7b70:6577:ec8:2542:9dd9"));
+    assert!(!matches("fc50:71b5:aebf:7b70:6577:ec8:2542:9dd8"));
+
+    assert!(Ip6Prefix::from_str("fc00::/129").is_err());
+    assert!(Ip6Prefix::from_str("fc00/8").is_err());
+    assert_eq!(Ip6Prefix::from_str("fc00::/8").unwrap().to_string(), "fc00::/8");
+}
@@ -0,0 +1,51 @@
+//! Graceful shutdown signalling
+
+use tokio::sync::watch;
+
+/// Signal telling the long-running tasks to wind down. Once triggered, stays triggered.
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, rx) = watch::channel(false);
+        Shutdown { tx, rx }
+    }
+
+    /// Start the shutdown. Triggering it again has no effect.
+    pub fn trigger(&self) {
+        let _ = self.tx.broadcast(true); // Can't fail because we hold the receiver
+    }
+
+    /// Whether the shutdown has started.
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Completes when the shutdown starts, immediately if it already has.
+    pub async fn wait(&self) {
+        let mut rx = self.rx.clone();
+        while let Some(false) = rx.recv().await {}
+    }
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    use std::time::Duration;
+    use tokio::time;
+
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.is_triggered());
+    assert!(time::timeout(Duration::from_millis(10), shutdown.wait()).await.is_err());
+
+    let waiting = shutdown.wait();
+    shutdown.trigger();
+    waiting.await;
+    assert!(shutdown.is_triggered());
+
+    // Waiting after the fact completes right away
+    shutdown.trigger();
+    shutdown.wait().await;
+}
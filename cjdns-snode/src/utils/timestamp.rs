@@ -13,11 +13,6 @@ pub fn make_timestamp(time: SystemTime) -> u64 {
     time_since_epoch.as_millis() as u64
 }
 
-/// Return current system time as cjdns timestamp
-pub fn current_timestamp() -> u64 {
-    make_timestamp(SystemTime::now())
-}
-
 /// Compute duration between two timestamps.
 /// It does not matter which of these timestamps is earlier.
 pub fn time_diff(t1: SystemTime, t2: SystemTime) -> Duration {